@@ -0,0 +1,283 @@
+use std::io::{self, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{
+    EncodableData, PacketDataType, TypedData,
+    limits::{DecodeLimits, Limit},
+    metadata::ColorMode,
+};
+
+/*
+
+Cell frame format:
+    columns : u16
+    rows : u16
+    color : u8 (ColorMode)
+    [cells, row-major]
+        eightbit: upper palette index : u8, lower palette index : u8
+        full: upper r, g, b : u8, lower r, g, b : u8
+
+Every cell is drawn as an upper half block (▀), fg = upper pixel, bg = lower pixel.
+
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellFrame {
+    pub columns: u16,
+    pub rows: u16,
+    pub color: ColorMode,
+    pub cells: Vec<u8>,
+}
+
+impl CellFrame {
+    pub fn new(columns: u16, rows: u16, color: ColorMode) -> Self {
+        Self {
+            columns,
+            rows,
+            color,
+            cells: Vec::with_capacity(
+                columns as usize * rows as usize * Self::bytes_per_cell(color),
+            ),
+        }
+    }
+
+    pub const fn bytes_per_cell(color: ColorMode) -> usize {
+        match color {
+            ColorMode::Full => 6,
+            ColorMode::EightBit => 2,
+        }
+    }
+
+    fn expected_len(&self) -> usize {
+        self.columns as usize * self.rows as usize * Self::bytes_per_cell(self.color)
+    }
+
+    /// The cell data the dimensions call for has to fit in `limits.max_packet_len`,
+    /// and is only allocated as it's actually read, so a frame lying about its size can't take more than the input has.
+    pub fn decode_with_limits<R: Read>(input: &mut R, limits: &DecodeLimits) -> io::Result<Self> {
+        let columns = input.read_u16::<LittleEndian>()?;
+        let rows = input.read_u16::<LittleEndian>()?;
+        let color = match input.read_u8()? {
+            0 => ColorMode::Full,
+            1 => ColorMode::EightBit,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "cell frame: unknown color mode",
+                ));
+            }
+        };
+
+        let len = columns as u64 * rows as u64 * Self::bytes_per_cell(color) as u64;
+        limits.check(Limit::PacketLen, len)?;
+
+        let mut cells = Vec::new();
+        input.take(len).read_to_end(&mut cells)?;
+        if cells.len() as u64 != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        Ok(CellFrame {
+            columns,
+            rows,
+            color,
+            cells,
+        })
+    }
+
+    /// upper and lower pixel of a cell, packed as described in the format above
+    pub fn cell(&self, column: u16, row: u16) -> &[u8] {
+        let bpc = Self::bytes_per_cell(self.color);
+        let start = (row as usize * self.columns as usize + column as usize) * bpc;
+        &self.cells[start..start + bpc]
+    }
+
+    pub fn to_ansi(&self, out: &mut impl Write) -> io::Result<()> {
+        self.write_cells(None, out)
+    }
+
+    /// only draws the cells that changed since `previous`, positioning the cursor explicitly.
+    pub fn to_ansi_delta(&self, previous: &CellFrame, out: &mut impl Write) -> io::Result<()> {
+        if previous.columns != self.columns
+            || previous.rows != self.rows
+            || previous.color != self.color
+        {
+            return self.to_ansi(out);
+        }
+
+        self.write_cells(Some(previous), out)
+    }
+
+    fn write_cells(&self, previous: Option<&CellFrame>, out: &mut impl Write) -> io::Result<()> {
+        let bpc = Self::bytes_per_cell(self.color);
+        let half = bpc / 2;
+
+        let mut buffer = itoa::Buffer::new();
+        let mut last_upper: Option<&[u8]> = None;
+        let mut last_lower: Option<&[u8]> = None;
+
+        // (column, row) the terminal cursor is at, if known
+        let mut cursor: Option<(u16, u16)> = None;
+
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                let cell = self.cell(column, row);
+
+                if let Some(previous) = previous {
+                    if previous.cell(column, row) == cell {
+                        continue;
+                    }
+
+                    if cursor != Some((column, row)) {
+                        out.write_all(b"\x1b[")?;
+                        out.write_all(buffer.format(row + 1).as_bytes())?;
+                        out.write_u8(b';')?;
+                        out.write_all(buffer.format(column + 1).as_bytes())?;
+                        out.write_u8(b'H')?;
+                    }
+                }
+
+                let (upper, lower) = cell.split_at(half);
+
+                if last_upper != Some(upper) {
+                    self.write_color(upper, true, &mut buffer, out)?;
+                }
+
+                if last_lower != Some(lower) {
+                    self.write_color(lower, false, &mut buffer, out)?;
+                }
+
+                out.write_all(b"\xE2\x96\x80")?;
+
+                cursor = Some((column + 1, row));
+                last_upper = Some(upper);
+                last_lower = Some(lower);
+            }
+
+            if previous.is_none() {
+                out.write_all(b"\x1b[1E")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_color(
+        &self,
+        pixel: &[u8],
+        foreground: bool,
+        buffer: &mut itoa::Buffer,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        match self.color {
+            ColorMode::EightBit => {
+                out.write_all(if foreground {
+                    b"\x1b[38;5;"
+                } else {
+                    b"\x1b[48;5;"
+                })?;
+                out.write_all(buffer.format(pixel[0]).as_bytes())?;
+            }
+            ColorMode::Full => {
+                out.write_all(if foreground {
+                    b"\x1b[38;2;"
+                } else {
+                    b"\x1b[48;2;"
+                })?;
+                out.write_all(buffer.format(pixel[0]).as_bytes())?;
+                out.write_u8(b';')?;
+                out.write_all(buffer.format(pixel[1]).as_bytes())?;
+                out.write_u8(b';')?;
+                out.write_all(buffer.format(pixel[2]).as_bytes())?;
+            }
+        }
+
+        out.write_u8(b'm')
+    }
+}
+
+impl EncodableData for CellFrame {
+    fn estimated_size(&self) -> Option<usize> {
+        Some(2 + 2 + 1 + self.cells.len())
+    }
+
+    fn encode_into<W: Write>(&self, out: &mut W) -> std::io::Result<u64> {
+        if self.cells.len() != self.expected_len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cell frame: cell data doesn't match dimensions",
+            ));
+        }
+
+        out.write_u16::<LittleEndian>(self.columns)?;
+        out.write_u16::<LittleEndian>(self.rows)?;
+        out.write_u8(self.color as u8)?;
+        out.write_all(&self.cells)?;
+
+        Ok(2 + 2 + 1 + self.cells.len() as u64)
+    }
+
+    fn decode_from<R: Read>(input: &mut R) -> std::io::Result<Self> {
+        CellFrame::decode_with_limits(input, &DecodeLimits::default())
+    }
+}
+
+impl TypedData for CellFrame {
+    const KIND: PacketDataType = PacketDataType::Video;
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use crate::{EncodableData, cells::CellFrame, limits::DecodeLimits, metadata::ColorMode};
+
+    #[test]
+    fn test_roundtrip() {
+        let mut frame = CellFrame::new(3, 2, ColorMode::EightBit);
+        frame
+            .cells
+            .extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+
+        let encoded = frame.encode_to_vec();
+        let decoded = CellFrame::decode_from(&mut encoded.as_slice()).unwrap();
+
+        assert_eq!(decoded, frame);
+    }
+
+    #[test]
+    fn test_decode_checks_size() {
+        // 65535 x 65535 full color cells, with hardly any data behind them
+        let mut huge = vec![0xFF, 0xFF, 0xFF, 0xFF, 0];
+        huge.extend_from_slice(&[0; 12]);
+
+        let err = CellFrame::decode_from(&mut huge.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let err = CellFrame::decode_with_limits(&mut huge.as_slice(), &DecodeLimits::unlimited())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_delta_only_draws_changes() {
+        let mut previous = CellFrame::new(2, 2, ColorMode::EightBit);
+        previous.cells.extend_from_slice(&[1, 1, 1, 1, 1, 1, 1, 1]);
+
+        let mut current = previous.clone();
+        current.cells[6] = 9; // upper pixel of the cell at column 1, row 1
+
+        let mut out = Vec::new();
+        current.to_ansi_delta(&previous, &mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b[2;2H\x1b[38;5;9m\x1b[48;5;1m▀"
+        );
+
+        let mut out = Vec::new();
+        current.to_ansi_delta(&current, &mut out).unwrap();
+        assert!(out.is_empty());
+    }
+}
@@ -0,0 +1,968 @@
+use std::io;
+
+use litemap::LiteMap;
+
+use crate::{
+    EncodableData, Error, Packet, SYNC_MARKER,
+    header::{HeaderCodec, HeaderVersion},
+    index::{INDEX_MAGIC, PacketIndex},
+    limits::{DecodeLimits, Limit, LimitExceeded},
+    metadata::{FormatData, TimeBase},
+    preamble::{Layout, MAGIC, Preamble},
+    seek::{SeekEntry, check_seektable_size, decode_seektable},
+};
+
+/*
+
+Reading a file without doing any IO, shared by every reader (blocking, async, memory mapped).
+
+A `Demuxer` gets handed the input starting at its `position`, and whether the input ends after it (`eof`).
+It answers with a `Step`:
+    - `Done`: the input goes on at `position`
+    - `Needs(len)`: call again with at least `len` bytes of input, or with `eof` set. never returned with `eof` set
+    - `Moved`: `position` changed (seeking, or past what was read), call again with the input starting there
+
+Nothing is taken from the input unless the whole thing (a header, a seek table section, ...) is there,
+calling again with more input starts over. Packet payloads are handed out by `payload`.
+
+*/
+
+// legacy files have no magic, anything claiming a bigger header than this isn't one of ours
+const MAX_LEGACY_HEADER_LEN: u64 = 16 << 20;
+
+// packet headers have no length prefix. only side data can make them grow,
+// nothing writes anywhere near this much of it
+const MAX_PACKET_HEADER_LEN: usize = 1 << 20;
+
+// (stream, entries) for every seek table of a section, in the order they're stored
+type SeekTables = Vec<(u8, Vec<SeekEntry>)>;
+
+/// What a `Demuxer` made of the input it was given, see the module source.
+#[derive(Debug)]
+pub enum Step<T> {
+    Done(T),
+    Needs(usize),
+    Moved,
+}
+
+// the value of a `Step::Done`, anything else is returned. `Step::Needs` counts from `$at` in the input
+macro_rules! done {
+    ($step:expr, $at:expr) => {
+        match $step {
+            Step::Done(value) => value,
+            Step::Needs(len) => return Ok(Step::Needs($at + len)),
+            Step::Moved => return Ok(Step::Moved),
+        }
+    };
+}
+
+// where calls that take several steps are at
+#[derive(Debug)]
+enum Stage {
+    // `read_header`: the preamble (or a legacy file's header length) comes first, then the header
+    Preamble,
+    Header,
+    Packets,
+    Seek(SeekWalk),
+    // `read_index`, going back to `back` once it's read
+    Index { back: u64 },
+}
+
+// `seek` walking the fragments of a fragmented file, `time` in ticks
+#[derive(Debug)]
+struct SeekWalk {
+    stream: u8,
+    time: i64,
+    // the best seek point so far, along with where the packets of its fragment start and their length
+    target: Option<(u64, u64, SeekEntry)>,
+    // where to go back to if there's none
+    back: u64,
+}
+
+/// Where a `Demuxer` is in the packets, see `restore`.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    position: u64,
+    fragment_end: Option<u64>,
+    headers: HeaderCodec,
+}
+
+/// The state of reading a file: what's been read from the header, where the packets are
+/// and what the next packet header depends on. See the module source for how it's driven.
+#[derive(Debug)]
+pub struct Demuxer {
+    // where the input handed to the next call starts
+    position: u64,
+    stage: Stage,
+    preamble: Option<Preamble>, // none for legacy files
+    time_bases: LiteMap<u8, TimeBase>,
+    seektables: LiteMap<u8, Vec<SeekEntry>>,
+    start_of_packets: u64,
+    // where the packet index would start, until it's been read
+    index_offset: Option<u64>,
+    index: Option<PacketIndex>,
+    // end of the current fragment, for fragmented files
+    fragment_end: Option<u64>,
+    headers: HeaderCodec,
+    limits: DecodeLimits,
+}
+
+impl Default for Demuxer {
+    fn default() -> Self {
+        Demuxer::new()
+    }
+}
+
+impl Demuxer {
+    pub fn new() -> Demuxer {
+        Demuxer {
+            position: 0,
+            stage: Stage::Preamble,
+            preamble: None,
+            time_bases: LiteMap::new(),
+            seektables: LiteMap::new(),
+            start_of_packets: 0,
+            index_offset: None,
+            index: None,
+            fragment_end: None,
+            headers: HeaderCodec::new(HeaderVersion::V1),
+            limits: DecodeLimits::default(),
+        }
+    }
+
+    /// where the input handed to the next call has to start
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Sizes read from the file are checked against these before anything gets allocated (or asked for) for them,
+    /// going over one fails with `Error::LimitExceeded`.
+    pub fn set_limits(&mut self, limits: DecodeLimits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+
+    /// format version and layout, `None` for legacy (v3.0) files
+    pub fn preamble(&self) -> Option<&Preamble> {
+        self.preamble.as_ref()
+    }
+
+    /// unit of `stream`'s packet timestamps and seek tables, microseconds until the header is read
+    pub fn time_base(&self, stream: u8) -> TimeBase {
+        self.time_bases.get(&stream).copied().unwrap_or_default()
+    }
+
+    /// seek points of `stream`, for fragmented files only those of the first fragment
+    pub fn seektable(&self, stream: u8) -> Option<&[SeekEntry]> {
+        self.seektables.get(&stream).map(Vec::as_slice)
+    }
+
+    /// the packet index, once `read_index` found one
+    pub fn index(&self) -> Option<&PacketIndex> {
+        self.index.as_ref()
+    }
+
+    fn is_fragmented(&self) -> bool {
+        self.preamble
+            .is_some_and(|preamble| preamble.layout == Layout::Fragmented)
+    }
+
+    // the trailer, for files that have one
+    fn end_of_packets(&self) -> Option<u64> {
+        match self.preamble?.layout {
+            Layout::Trailer { trailer_offset } => Some(trailer_offset),
+            Layout::Fragmented => None,
+        }
+    }
+
+    /// The preamble and header, starting at the beginning of the file. Returned along with
+    /// where the header starts, for errors about what's in it.
+    pub fn read_header(
+        &mut self,
+        input: &[u8],
+        eof: bool,
+    ) -> crate::Result<Step<(FormatData, u64)>> {
+        if let Stage::Preamble = self.stage {
+            return self.read_preamble(input, eof);
+        }
+
+        let Some(len) = input.first_chunk::<8>() else {
+            return needs(8, eof, || {
+                io::Error::from(io::ErrorKind::UnexpectedEof).into()
+            });
+        };
+
+        let header_len = u64::from_le_bytes(*len);
+        let offset = self.position + 8;
+        self.limits
+            .check(Limit::HeaderLen, header_len)
+            .map_err(|limit| Error::LimitExceeded {
+                offset,
+                stream: None,
+                limit,
+            })?;
+
+        let end = 8usize.saturating_add(header_len as usize);
+        let Some(data) = input.get(8..end) else {
+            return needs(end, eof, || Error::HeaderDecode {
+                offset,
+                reason: "header is cut off".to_string(),
+            });
+        };
+
+        let header = decode_format_data(data, offset)?;
+        self.time_bases = read_time_bases(&header, offset)?;
+        self.position += end as u64;
+        self.stage = Stage::Packets;
+
+        Ok(Step::Done((header, offset)))
+    }
+
+    fn read_preamble<T>(&mut self, input: &[u8], eof: bool) -> crate::Result<Step<T>> {
+        let Some(magic) = input.first_chunk::<8>() else {
+            return needs(8, eof, || {
+                io::Error::from(io::ErrorKind::UnexpectedEof).into()
+            });
+        };
+
+        if *magic != MAGIC {
+            // legacy files start right away with the header length
+            if u64::from_le_bytes(*magic) > MAX_LEGACY_HEADER_LEN {
+                return Err(Error::BadMagic);
+            }
+
+            self.stage = Stage::Header;
+            return Ok(Step::Moved);
+        }
+
+        let preamble = match Preamble::decode_after_magic(&mut &input[MAGIC.len()..]) {
+            Ok(preamble) => preamble,
+            Err(Error::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return needs(input.len() + 1, eof, || e.into());
+            }
+            Err(e) => return Err(e),
+        };
+
+        self.start_of_packets = preamble.estimated_size().unwrap() as u64;
+        self.position = match preamble.layout {
+            Layout::Trailer { trailer_offset } => trailer_offset,
+            Layout::Fragmented => self.start_of_packets,
+        };
+        self.headers = HeaderCodec::new(HeaderVersion::for_format_version(preamble.major));
+        self.preamble = Some(preamble);
+        self.stage = Stage::Header;
+
+        Ok(Step::Moved)
+    }
+
+    /// The seek tables following the header. Fragmented files only have those of their first fragment.
+    /// Goes on at the first packet.
+    pub fn read_seektables(&mut self, input: &[u8], eof: bool) -> crate::Result<Step<SeekTables>> {
+        let (seektables, mut len) = done!(
+            parse_seektables(input, eof, self.position, &self.limits)?,
+            0
+        );
+
+        if self.is_fragmented() {
+            let offset = self.position + len as u64;
+            let packets_len = done!(parse_packets_len(&input[len..], eof, offset)?, len);
+            len += 8;
+            self.fragment_end = Some((offset + 8).saturating_add(packets_len));
+        }
+
+        self.position += len as u64;
+        self.seektables = seektables.iter().cloned().collect();
+
+        // packets sit between the preamble and the trailer, or follow the seek tables (legacy files).
+        // fragmented files keep the first fragment's offset, that's where seeking starts looking
+        if self.end_of_packets().is_some() {
+            // the packet index follows the trailer's seek tables, it's only read when asked for
+            self.index_offset = Some(self.position);
+            self.position = self.start_of_packets;
+        } else if !self.is_fragmented() {
+            self.start_of_packets = self.position;
+        }
+
+        Ok(Step::Done(seektables))
+    }
+
+    /// The next packet header, and where it starts. Its payload follows at `position`, see `payload`.
+    /// Fails with `Error::EndOfStream` once there are no packets left.
+    pub fn next_packet(&mut self, input: &[u8], eof: bool) -> crate::Result<Step<(u64, Packet)>> {
+        // whatever was left unfinished, the packets go on here
+        self.stage = Stage::Packets;
+
+        if self
+            .end_of_packets()
+            .is_some_and(|end| self.position >= end)
+        {
+            return Err(Error::EndOfStream);
+        }
+
+        // skips the repeated header + seek tables at the start of the next fragment
+        if self.fragment_end.is_some_and(|end| self.position >= end) {
+            let Some(fragment) = done!(parse_fragment(input, eof, self.position, &self.limits)?, 0)
+            else {
+                // fragmented files just stop after their last fragment
+                return Err(Error::EndOfStream);
+            };
+
+            self.position += fragment.len as u64;
+            self.fragment_end = Some(self.position.saturating_add(fragment.packets_len));
+            self.headers.reset();
+            return Ok(Step::Moved);
+        }
+
+        let offset = self.position;
+        let Some(&stream) = input.first() else {
+            return needs(1, eof, || Error::EndOfStream);
+        };
+
+        // sync markers start with the reserved stream index
+        if stream == SYNC_MARKER[0] {
+            let Some(marker) = input.first_chunk::<8>() else {
+                return needs(SYNC_MARKER.len(), eof, || Error::TruncatedPacket {
+                    offset,
+                    stream: None,
+                });
+            };
+
+            if *marker != SYNC_MARKER {
+                return Err(Error::DamagedPacket {
+                    offset,
+                    stream: None,
+                    reason: "damaged sync marker".to_string(),
+                });
+            }
+
+            // the packet after a marker always has an absolute timestamp
+            self.position += SYNC_MARKER.len() as u64;
+            self.headers.reset();
+            return Ok(Step::Moved);
+        }
+
+        // decoding only keeps the timestamp once the whole header is there
+        let mut rest = input;
+        let packet = match self.headers.decode_from(&mut rest) {
+            Ok(packet) => packet,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && !eof => {
+                if input.len() >= MAX_PACKET_HEADER_LEN {
+                    return Err(Error::DamagedPacket {
+                        offset,
+                        stream: Some(stream),
+                        reason: "packet header is too long".to_string(),
+                    });
+                }
+
+                return Ok(Step::Needs(input.len() + 1));
+            }
+            Err(e) => return Err(packet_error(offset, Some(stream), e)),
+        };
+
+        self.limits
+            .check(Limit::PacketLen, packet.data_len)
+            .map_err(|limit| Error::LimitExceeded {
+                offset,
+                stream: Some(stream),
+                limit,
+            })?;
+
+        let payload_start = offset + (input.len() - rest.len()) as u64;
+        let limit = self.fragment_end.or(self.end_of_packets());
+        if limit.is_some_and(|limit| payload_start.saturating_add(packet.data_len) > limit) {
+            return Err(Error::DamagedPacket {
+                offset,
+                stream: Some(stream),
+                reason: "packet runs past the end of the packet data".to_string(),
+            });
+        }
+
+        self.position = payload_start;
+        Ok(Step::Done((offset, packet)))
+    }
+
+    /// The payload of the packet `next_packet` just returned (starting at `offset`), out of input holding all of it.
+    pub fn payload<'a>(
+        &mut self,
+        offset: u64,
+        packet: &Packet,
+        input: &'a [u8],
+    ) -> crate::Result<&'a [u8]> {
+        let data = input
+            .get(..packet.data_len as usize)
+            .ok_or(Error::TruncatedPacket {
+                offset,
+                stream: Some(packet.stream),
+            })?;
+
+        self.position += packet.data_len;
+        Ok(data)
+    }
+
+    /// Skips ahead to the next sync marker after a damaged packet, without running past the current fragment
+    /// or the packet data.
+    pub fn resync(&mut self, input: &[u8], eof: bool) -> crate::Result<Step<()>> {
+        // a damaged fragment header loses track of the fragments, what follows is read like one long fragment
+        if self.fragment_end.is_some_and(|end| self.position >= end) {
+            self.fragment_end = None;
+        }
+
+        let limit = self.fragment_end.or(self.end_of_packets());
+        let scan_len = limit.map_or(input.len(), |limit| {
+            input
+                .len()
+                .min(limit.saturating_sub(self.position) as usize)
+        });
+        let scanned = &input[..scan_len];
+
+        if let Some(at) = scanned
+            .windows(SYNC_MARKER.len())
+            .position(|window| window == SYNC_MARKER)
+        {
+            self.position += (at + SYNC_MARKER.len()) as u64;
+            self.headers.reset();
+            return Ok(Step::Done(()));
+        }
+
+        if eof || limit.is_some_and(|limit| self.position + scan_len as u64 >= limit) {
+            self.position += scan_len as u64;
+            self.headers.reset();
+            return Ok(Step::Done(()));
+        }
+
+        // the end of what's there could be the start of a marker
+        let skipped = scan_len.saturating_sub(SYNC_MARKER.len() - 1);
+        if skipped == 0 {
+            return Ok(Step::Needs(input.len() + 1));
+        }
+
+        self.position += skipped as u64;
+        Ok(Step::Moved)
+    }
+
+    /// Moves to the last seek point of `stream` at or before `time` (falling back to the first one) and returns
+    /// its timestamp, both in microseconds. Packets of every stream are read from there on.
+    pub fn seek(
+        &mut self,
+        stream: u8,
+        time: i64,
+        input: &[u8],
+        eof: bool,
+    ) -> crate::Result<Step<i64>> {
+        let time_base = self.time_base(stream);
+        let ticks = time_base.from_micros(time);
+
+        if !self.is_fragmented() {
+            let entry = self
+                .seektables
+                .get(&stream)
+                .and_then(|entries| seek_point(entries, ticks))
+                .ok_or(Error::NoSeekPoints { stream })?;
+
+            self.position = self.start_of_packets.saturating_add_signed(entry.location);
+            self.headers.reset();
+
+            return Ok(Step::Done(time_base.to_micros(entry.ts)));
+        }
+
+        // fragmented files have no global seek table, so walk the fragments and use their own tables
+        if !matches!(&self.stage, Stage::Seek(walk) if walk.stream == stream && walk.time == ticks)
+        {
+            self.stage = Stage::Seek(SeekWalk {
+                stream,
+                time: ticks,
+                target: None,
+                back: self.position,
+            });
+            self.position = self.start_of_packets; // the first fragment, right after the preamble
+            return Ok(Step::Moved);
+        }
+
+        let fragment = match parse_fragment(input, eof, self.position, &self.limits) {
+            Ok(step) => done!(step, 0),
+            Err(e) => {
+                self.stage = Stage::Packets;
+                return Err(e);
+            }
+        };
+
+        let Stage::Seek(mut walk) = std::mem::replace(&mut self.stage, Stage::Packets) else {
+            return Ok(Step::Moved);
+        };
+
+        if let Some(fragment) = fragment {
+            let start = self.position + fragment.len as u64;
+            let entries = fragment
+                .seektables
+                .iter()
+                .find(|(index, _)| *index == stream)
+                .map(|(_, entries)| entries.as_slice());
+
+            let passed = walk.target.is_some()
+                && entries
+                    .and_then(|entries| entries.first())
+                    .is_some_and(|entry| entry.ts > ticks);
+
+            if !passed {
+                if let Some(entry) = entries.and_then(|entries| seek_point(entries, ticks)) {
+                    walk.target = Some((start, fragment.packets_len, entry));
+                }
+
+                self.position = start.saturating_add(fragment.packets_len);
+                self.stage = Stage::Seek(walk);
+                return Ok(Step::Moved);
+            }
+        }
+
+        let Some((start, packets_len, entry)) = walk.target else {
+            self.position = walk.back;
+            return Err(Error::NoSeekPoints { stream });
+        };
+
+        self.position = start.saturating_add_signed(entry.location);
+        self.fragment_end = Some(start.saturating_add(packets_len));
+        self.headers.reset();
+
+        Ok(Step::Done(time_base.to_micros(entry.ts)))
+    }
+
+    /// Reads the packet index following the trailer's seek tables, the first time it's called. `index` has it
+    /// afterwards, it stays `None` for fragmented and legacy files, and for files written without one.
+    /// Goes back to where it was once done.
+    pub fn read_index(&mut self, input: &[u8], eof: bool) -> crate::Result<Step<()>> {
+        let Some(offset) = self.index_offset else {
+            return Ok(Step::Done(()));
+        };
+
+        let Stage::Index { back } = self.stage else {
+            self.stage = Stage::Index {
+                back: self.position,
+            };
+            self.position = offset;
+            return Ok(Step::Moved);
+        };
+
+        let index = match parse_index(input, eof, &self.limits) {
+            Ok(step) => Ok(done!(step, 0)),
+            Err(e) => Err(index_error(offset, e)),
+        };
+
+        self.index_offset = None;
+        self.position = back;
+        self.stage = Stage::Packets;
+
+        self.index = index?;
+        Ok(Step::Done(()))
+    }
+
+    /// Goes on at the packet at position `entry` of the index, like after a `seek`. Fails with `Error::NoIndex`
+    /// for files without an index, or if it hasn't been read yet.
+    pub fn seek_indexed(&mut self, entry: usize) -> crate::Result<()> {
+        let relative_to_stream = self.headers.relative_to_stream();
+        let index = self.index.as_ref().ok_or(Error::NoIndex)?;
+        let Some(target) = index.entries().get(entry).copied() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no index entry {entry}, there are {}", index.len()),
+            )
+            .into());
+        };
+
+        // the timestamp may be relative to the packet stored before it (of the same stream, for v3 headers)
+        let previous = index.entries()[..entry]
+            .iter()
+            .rfind(|previous| !relative_to_stream || previous.stream == target.stream)
+            .map(|previous| previous.timestamp as u64);
+
+        self.position = self.start_of_packets.saturating_add(target.offset);
+        self.headers.resume_after(target.stream, previous);
+
+        Ok(())
+    }
+
+    /// Goes back to the first packet of the file, attachments are stored in front of every other packet.
+    pub fn rewind(&mut self) {
+        // fragmented files start with the header and seek tables of the first fragment
+        self.position = self.start_of_packets;
+        self.fragment_end = self.is_fragmented().then_some(self.start_of_packets);
+        self.headers.reset();
+    }
+
+    /// Goes on at the packet header at `offset`. There's no telling which fragment that's in, only
+    /// the end of the file bounds the packet, and its timestamp has to be absolute.
+    pub fn seek_to_packet(&mut self, offset: u64) {
+        self.position = offset;
+        self.fragment_end = None;
+        self.headers.reset();
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            position: self.position,
+            fragment_end: self.fragment_end,
+            headers: self.headers.clone(),
+        }
+    }
+
+    /// goes back to where `checkpoint` was taken
+    pub fn restore(&mut self, checkpoint: Checkpoint) {
+        self.position = checkpoint.position;
+        self.fragment_end = checkpoint.fragment_end;
+        self.headers = checkpoint.headers;
+    }
+}
+
+// asks for `len` bytes of input, failing with `error` if there won't be more
+fn needs<T>(len: usize, eof: bool, error: impl FnOnce() -> Error) -> crate::Result<Step<T>> {
+    if eof {
+        return Err(error());
+    }
+
+    Ok(Step::Needs(len))
+}
+
+// last entry at or before `time`, or the first one if `time` comes before all of them
+fn seek_point(entries: &[SeekEntry], time: i64) -> Option<SeekEntry> {
+    let idx = entries.partition_point(|entry| entry.ts <= time);
+    entries.get(idx.saturating_sub(1)).copied()
+}
+
+// a seek table section starting at `offset`, along with its length. the sizes of every table are checked before
+// asking for what follows them, and nothing is decoded before all of it is there
+fn parse_seektables(
+    input: &[u8],
+    eof: bool,
+    offset: u64,
+    limits: &DecodeLimits,
+) -> crate::Result<Step<(SeekTables, usize)>> {
+    let cut_off = |at: usize, stream| {
+        seektable_error(
+            offset + at as u64,
+            stream,
+            io::ErrorKind::UnexpectedEof.into(),
+        )
+    };
+
+    let Some(&n_seektables) = input.first() else {
+        return needs(1, eof, || cut_off(0, None));
+    };
+
+    // (stream, where the table starts, where its data starts and ends, entries)
+    let mut tables = Vec::with_capacity(n_seektables as usize);
+    let mut end = 1;
+    for _ in 0..n_seektables {
+        let start = end;
+        let Some(&stream) = input.get(start) else {
+            return needs(start + 1, eof, || cut_off(start, None));
+        };
+
+        let Some(sizes) = input.get(start + 1..start + 17) else {
+            return needs(start + 17, eof, || cut_off(start, Some(stream)));
+        };
+
+        let len_bytes = u64::from_le_bytes(sizes[..8].try_into().unwrap());
+        let len_elements = u64::from_le_bytes(sizes[8..].try_into().unwrap());
+        check_seektable_size(len_bytes, len_elements, limits)
+            .map_err(|e| seektable_error(offset + start as u64, Some(stream), e))?;
+
+        end = (start + 17).saturating_add(len_bytes as usize);
+        if input.len() < end {
+            return needs(end, eof, || cut_off(start, Some(stream)));
+        }
+
+        tables.push((stream, start, start + 17..end, len_elements));
+    }
+
+    let seektables = tables
+        .into_iter()
+        .map(|(stream, start, data, len_elements)| {
+            decode_seektable(&input[data], len_elements)
+                .map(|entries| (stream, entries))
+                .map_err(|e| seektable_error(offset + start as u64, Some(stream), e))
+        })
+        .collect::<crate::Result<_>>()?;
+
+    Ok(Step::Done((seektables, end)))
+}
+
+// the length of the packets following a fragment's seek tables
+fn parse_packets_len(input: &[u8], eof: bool, offset: u64) -> crate::Result<Step<u64>> {
+    match input.first_chunk::<8>() {
+        Some(len) => Ok(Step::Done(u64::from_le_bytes(*len))),
+        None => needs(8, eof, || {
+            seektable_error(offset, None, io::ErrorKind::UnexpectedEof.into())
+        }),
+    }
+}
+
+// what comes before a fragment's packets
+struct Fragment {
+    seektables: SeekTables,
+    packets_len: u64,
+    // of the header, seek tables and packets length
+    len: usize,
+}
+
+// the fragment starting at `offset`, up to its packets. its header only repeats the first one, it's skipped.
+// `None` if the input ends where it would start
+fn parse_fragment(
+    input: &[u8],
+    eof: bool,
+    offset: u64,
+    limits: &DecodeLimits,
+) -> crate::Result<Step<Option<Fragment>>> {
+    let Some(len) = input.first_chunk::<8>() else {
+        return Ok(if eof {
+            Step::Done(None)
+        } else {
+            Step::Needs(8)
+        });
+    };
+
+    let header_len = u64::from_le_bytes(*len);
+    limits
+        .check(Limit::HeaderLen, header_len)
+        .map_err(|limit| Error::LimitExceeded {
+            offset,
+            stream: None,
+            limit,
+        })?;
+
+    let tables_start = 8usize.saturating_add(header_len as usize);
+    if input.len() < tables_start {
+        return needs(tables_start, eof, || Error::HeaderDecode {
+            offset,
+            reason: "fragment header is cut off".to_string(),
+        });
+    }
+
+    let (seektables, tables_len) = done!(
+        parse_seektables(
+            &input[tables_start..],
+            eof,
+            offset + tables_start as u64,
+            limits
+        )?,
+        tables_start
+    );
+
+    let len_start = tables_start + tables_len;
+    let packets_len = done!(
+        parse_packets_len(&input[len_start..], eof, offset + len_start as u64)?,
+        len_start
+    );
+
+    Ok(Step::Done(Some(Fragment {
+        seektables,
+        packets_len,
+        len: len_start + 8,
+    })))
+}
+
+// `None` when the trailer ends right after the seek tables (files without an index, or older than 5.1)
+fn parse_index(
+    input: &[u8],
+    eof: bool,
+    limits: &DecodeLimits,
+) -> io::Result<Step<Option<PacketIndex>>> {
+    let Some(magic) = input.first_chunk::<4>() else {
+        return Ok(if eof {
+            Step::Done(None)
+        } else {
+            Step::Needs(4)
+        });
+    };
+
+    if *magic != INDEX_MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "bad magic"));
+    }
+
+    match PacketIndex::decode_after_magic(&mut &input[4..], limits) {
+        Ok(index) => Ok(Step::Done(Some(index))),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && !eof => {
+            // the sizes are checked against the limits before the entries are read, ask for all of them at once
+            let len_bytes = input
+                .get(12..)
+                .and_then(|sizes| sizes.first_chunk::<8>())
+                .map_or(0, |len| u64::from_le_bytes(*len));
+
+            Ok(Step::Needs(
+                20usize
+                    .saturating_add(len_bytes as usize)
+                    .max(input.len() + 1),
+            ))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// DER-decoded `FormatData` from `offset`, rejecting features this crate doesn't know
+fn decode_format_data(data: &[u8], offset: u64) -> crate::Result<FormatData> {
+    let header = rasn::der::decode::<FormatData>(data).map_err(|e| Error::HeaderDecode {
+        offset,
+        reason: e.to_string(),
+    })?;
+
+    if header.unknown_features() != 0 {
+        return Err(Error::UnsupportedFeatures {
+            flags: header.unknown_features(),
+        });
+    }
+
+    Ok(header)
+}
+
+fn read_time_bases(header: &FormatData, offset: u64) -> crate::Result<LiteMap<u8, TimeBase>> {
+    let mut time_bases = LiteMap::new();
+    for stream in &header.tracks {
+        let time_base = stream.time_base();
+        if !time_base.is_valid() {
+            return Err(Error::HeaderDecode {
+                offset,
+                reason: format!("stream {}: invalid time base {time_base}", stream.index),
+            });
+        }
+
+        time_bases.insert(stream.index, time_base);
+    }
+
+    Ok(time_bases)
+}
+
+fn seektable_error(offset: u64, stream: Option<u8>, error: io::Error) -> Error {
+    if let Some(limit) = limit_exceeded(&error) {
+        return Error::LimitExceeded {
+            offset,
+            stream,
+            limit,
+        };
+    }
+
+    match error.kind() {
+        io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData => Error::SeekTableDecode {
+            offset,
+            stream,
+            reason: error.to_string(),
+        },
+        _ => Error::Io(error),
+    }
+}
+
+fn index_error(offset: u64, error: io::Error) -> Error {
+    if let Some(limit) = limit_exceeded(&error) {
+        return Error::LimitExceeded {
+            offset,
+            stream: None,
+            limit,
+        };
+    }
+
+    match error.kind() {
+        io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData => Error::IndexDecode {
+            offset,
+            reason: error.to_string(),
+        },
+        _ => Error::Io(error),
+    }
+}
+
+fn packet_error(offset: u64, stream: Option<u8>, error: io::Error) -> Error {
+    if let Some(limit) = limit_exceeded(&error) {
+        return Error::LimitExceeded {
+            offset,
+            stream,
+            limit,
+        };
+    }
+
+    match error.kind() {
+        io::ErrorKind::UnexpectedEof => Error::TruncatedPacket { offset, stream },
+        io::ErrorKind::InvalidData => Error::DamagedPacket {
+            offset,
+            stream,
+            reason: error.to_string(),
+        },
+        _ => Error::Io(error),
+    }
+}
+
+fn limit_exceeded(error: &io::Error) -> Option<LimitExceeded> {
+    error.get_ref()?.downcast_ref::<LimitExceeded>().copied()
+}
+
+/// Input read ahead of a `Demuxer`, for readers doing IO: they read into `spare`, and hand out `available`
+/// after moving it to where the demuxer goes on.
+#[derive(Debug, Default)]
+pub struct ReadBuffer {
+    buffer: Vec<u8>,
+    // what's left is `buffer[start..end]`, `buffer[start]` sits at `position` in the file
+    start: usize,
+    end: usize,
+    position: u64,
+    // the input ends after `end`
+    eof: bool,
+}
+
+impl ReadBuffer {
+    pub fn new() -> ReadBuffer {
+        ReadBuffer::default()
+    }
+
+    /// what's buffered from `position` on
+    pub fn available(&self) -> &[u8] {
+        &self.buffer[self.start..self.end]
+    }
+
+    pub fn is_eof(&self) -> bool {
+        self.eof
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Moves to `position` if it's buffered (or right after what is), returns whether it was.
+    pub fn move_to(&mut self, position: u64) -> bool {
+        let buffer_start = self.position - self.start as u64;
+        if !(buffer_start..=buffer_start + self.end as u64).contains(&position) {
+            return false;
+        }
+
+        self.start = (position - buffer_start) as usize;
+        self.position = position;
+        true
+    }
+
+    /// Drops what's buffered, after the input was moved to `position`.
+    pub fn reset(&mut self, position: u64) {
+        self.start = 0;
+        self.end = 0;
+        self.position = position;
+        self.eof = false;
+    }
+
+    /// Room for reading up to `len` more bytes into, how many were read goes to `filled`.
+    pub fn spare(&mut self, len: usize) -> &mut [u8] {
+        // what's been moved past is only kept around until the room is needed
+        if self.start > 0 {
+            self.buffer.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+
+        if self.buffer.len() < self.end + len {
+            self.buffer.resize(self.end + len, 0);
+        }
+
+        &mut self.buffer[self.end..self.end + len]
+    }
+
+    /// `len` bytes were read into `spare`, none means the input ended.
+    pub fn filled(&mut self, len: usize) {
+        self.end += len;
+        self.eof = len == 0;
+    }
+}
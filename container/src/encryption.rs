@@ -0,0 +1,101 @@
+use crate::{EncodableData, Packet, side_data};
+
+/*
+
+Encrypted payloads (since 6.6, `metadata::features::ENCRYPTED`):
+
+Every payload is sealed with XChaCha20-Poly1305 after compression, the 16 byte tag follows the ciphertext
+(so `data_len` and <DCLE> don't match up anymore). Packets without payload (`Repeat`) stay as they are.
+The header, seek tables and packet headers are left readable.
+
+    side data <NONC> NONCE    24 bytes, random per packet
+    side data <KYID> KEY_ID   which key the payload is sealed with, absent for a file's only key
+
+Associated data (`associated_data`), so a payload can't be moved and its header can't be changed:
+    the v1 encoding of the packet header without <NONC> and <CRCC>, with `data_len` of the sealed payload
+    (so the same on both sides: the encoder seals after setting <KYID>, the packet index and the length)
+
+*/
+
+pub const KEY_LEN: usize = 32;
+pub const NONCE_LEN: usize = 24;
+/// the Poly1305 tag appended to every payload
+pub const TAG_LEN: usize = 16;
+
+pub fn associated_data(packet: &Packet) -> Vec<u8> {
+    let mut header = packet.clone();
+    header.side_data.remove(&side_data::NONCE);
+    header.side_data.remove(&side_data::CHECKSUM);
+
+    let mut encoded = Vec::with_capacity(packet.estimated_size().unwrap());
+    header.write_v1(&mut encoded, false).unwrap();
+    encoded
+}
+
+/// a key written as 64 hex digits, like in a key file, whitespace around it is ignored
+pub fn parse_key(text: &str) -> Option<[u8; KEY_LEN]> {
+    let text = text.trim();
+    if text.len() != KEY_LEN * 2 || !text.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let mut key = [0; KEY_LEN];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).ok()?;
+    }
+
+    Some(key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{KEY_LEN, NONCE_LEN, associated_data, parse_key};
+    use crate::{Packet, side_data};
+
+    #[test]
+    fn test_parse_key() {
+        let hex = "00112233445566778899aabbccddeeff00112233445566778899AABBCCDDEEFF\n";
+        let key = parse_key(hex).unwrap();
+        assert_eq!(key[..4], [0x00, 0x11, 0x22, 0x33]);
+        assert_eq!(key[KEY_LEN - 1], 0xff);
+
+        assert_eq!(parse_key(&hex[..62]), None);
+        assert_eq!(parse_key(&hex.replace('a', "g")), None);
+        assert_eq!(parse_key(&hex.replacen("00", "+0", 1)), None);
+    }
+
+    #[test]
+    fn test_associated_data_covers_header() {
+        let mut packet = Packet::builder()
+            .stream(1)
+            .timestamp(40_000)
+            .duration(40_000)
+            .build();
+        packet.packet_idx = 7;
+        packet.data_len = 100;
+        packet.side_data.insert(side_data::KEY_ID, b"main".to_vec());
+        let data = associated_data(&packet);
+
+        // nonce and checksum are added after sealing
+        let mut sealed = packet.clone();
+        sealed
+            .side_data
+            .insert(side_data::NONCE, vec![0; NONCE_LEN]);
+        sealed.side_data.insert(side_data::CHECKSUM, vec![0; 4]);
+        assert_eq!(associated_data(&sealed), data);
+
+        let mut moved = packet.clone();
+        moved.packet_idx = 8;
+        assert_ne!(associated_data(&moved), data);
+
+        let mut stretched = packet.clone();
+        stretched.duration = 80_000;
+        assert_ne!(associated_data(&stretched), data);
+
+        let mut relabelled = packet.clone();
+        relabelled
+            .side_data
+            .set_u64(side_data::DECOMPRESSED_LEN, 1000);
+        assert_ne!(associated_data(&relabelled), data);
+    }
+}
@@ -0,0 +1,277 @@
+use std::{fmt::Display, io};
+
+use crate::limits;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Everything that can go wrong reading a file. Offsets are in bytes from the start of the input,
+/// for packets they point at the start of the packet header.
+#[derive(Debug)]
+pub enum Error {
+    /// the underlying reader failed
+    Io(io::Error),
+    /// no more packets, the file ended cleanly
+    EndOfStream,
+    /// neither the magic nor anything a legacy file could start with
+    BadMagic,
+    UnsupportedVersion {
+        major: u16,
+        minor: u16,
+    },
+    /// `FormatData` feature flags this crate doesn't know
+    UnsupportedFeatures {
+        flags: u64,
+    },
+    /// the preamble or the DER-encoded `FormatData`
+    HeaderDecode {
+        offset: u64,
+        reason: String,
+    },
+    SeekTableDecode {
+        offset: u64,
+        stream: Option<u8>,
+        reason: String,
+    },
+    /// the packet index following the seek tables
+    IndexDecode {
+        offset: u64,
+        reason: String,
+    },
+    /// seeking on a stream without seek points
+    NoSeekPoints {
+        stream: u8,
+    },
+    /// random access by index on a file without a packet index
+    NoIndex,
+    /// no attachment packet for the stream at the start of the packet data
+    NoAttachment {
+        stream: u8,
+    },
+    /// the input ended in the middle of a packet
+    TruncatedPacket {
+        offset: u64,
+        stream: Option<u8>,
+    },
+    /// a packet header that makes no sense, a damaged sync marker or a checksum mismatch
+    DamagedPacket {
+        offset: u64,
+        stream: Option<u8>,
+        reason: String,
+    },
+    /// a packet compressed with a method its stream doesn't declare (or that doesn't exist)
+    UnknownCompression {
+        offset: Option<u64>,
+        stream: u8,
+        method: u8,
+    },
+    /// `offset` is `None` when raised outside of a reader (by a `DecoderProcessor` on its own)
+    Decompression {
+        offset: Option<u64>,
+        stream: u8,
+        reason: String,
+    },
+    /// an encrypted payload without a key for it, or one that doesn't authenticate (tampered with or the wrong key)
+    Decryption {
+        offset: Option<u64>,
+        stream: u8,
+        reason: String,
+    },
+    LimitExceeded {
+        offset: u64,
+        stream: Option<u8>,
+        limit: limits::LimitExceeded,
+    },
+}
+
+impl Error {
+    /// the stream the error happened on, if it's known
+    pub fn stream(&self) -> Option<u8> {
+        match self {
+            Error::SeekTableDecode { stream, .. }
+            | Error::TruncatedPacket { stream, .. }
+            | Error::DamagedPacket { stream, .. }
+            | Error::LimitExceeded { stream, .. } => *stream,
+            Error::NoSeekPoints { stream }
+            | Error::NoAttachment { stream }
+            | Error::UnknownCompression { stream, .. }
+            | Error::Decompression { stream, .. }
+            | Error::Decryption { stream, .. } => Some(*stream),
+            _ => None,
+        }
+    }
+
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            Error::HeaderDecode { offset, .. }
+            | Error::IndexDecode { offset, .. }
+            | Error::SeekTableDecode { offset, .. }
+            | Error::TruncatedPacket { offset, .. }
+            | Error::DamagedPacket { offset, .. }
+            | Error::LimitExceeded { offset, .. } => Some(*offset),
+            Error::UnknownCompression { offset, .. }
+            | Error::Decompression { offset, .. }
+            | Error::Decryption { offset, .. } => *offset,
+            _ => None,
+        }
+    }
+
+    /// fills in the offset of errors raised without knowing where in the file they are
+    pub fn at_offset(mut self, at: u64) -> Error {
+        if let Error::UnknownCompression { offset, .. }
+        | Error::Decompression { offset, .. }
+        | Error::Decryption { offset, .. } = &mut self
+        {
+            offset.get_or_insert(at);
+        }
+
+        self
+    }
+
+    /// damage to a part of the file that can be skipped, reading can go on after the next sync marker
+    pub fn is_damage(&self) -> bool {
+        matches!(
+            self,
+            Error::DamagedPacket { .. }
+                | Error::UnknownCompression { .. }
+                | Error::Decompression { .. }
+                | Error::Decryption { .. }
+                | Error::LimitExceeded { .. }
+                | Error::SeekTableDecode { .. }
+        )
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::EndOfStream => write!(f, "end of stream"),
+            Error::BadMagic => write!(f, "not an ansi.moe file (bad magic)"),
+            Error::UnsupportedVersion { major, minor } => write!(
+                f,
+                "file format version {major}.{minor} is not supported (expected {}.x to {}.x)",
+                crate::preamble::OLDEST_MAJOR,
+                crate::preamble::VERSION_MAJOR
+            ),
+            Error::UnsupportedFeatures { flags } => write!(
+                f,
+                "file uses features this reader doesn't support (flags {flags:#x})"
+            ),
+            Error::HeaderDecode { offset, reason } => {
+                write!(f, "header at byte {offset}: {reason}")
+            }
+            Error::SeekTableDecode {
+                offset,
+                stream,
+                reason,
+            } => {
+                write!(f, "seek table at byte {offset}")?;
+                if let Some(stream) = stream {
+                    write!(f, " (stream {stream})")?;
+                }
+                write!(f, ": {reason}")
+            }
+            Error::IndexDecode { offset, reason } => {
+                write!(f, "packet index at byte {offset}: {reason}")
+            }
+            Error::NoSeekPoints { stream } => write!(f, "no seek points for stream {stream}"),
+            Error::NoIndex => write!(f, "file has no packet index"),
+            Error::NoAttachment { stream } => write!(f, "no attachment for stream {stream}"),
+            Error::TruncatedPacket { offset, stream } => {
+                write!(f, "packet at byte {offset}")?;
+                if let Some(stream) = stream {
+                    write!(f, " (stream {stream})")?;
+                }
+                write!(f, " is cut off")
+            }
+            Error::DamagedPacket {
+                offset,
+                stream,
+                reason,
+            } => {
+                write!(f, "packet at byte {offset}")?;
+                if let Some(stream) = stream {
+                    write!(f, " (stream {stream})")?;
+                }
+                write!(f, ": {reason}")
+            }
+            Error::UnknownCompression {
+                offset,
+                stream,
+                method,
+            } => {
+                write!(f, "stream {stream}: unknown compression method {method}")?;
+                if let Some(offset) = offset {
+                    write!(f, " at byte {offset}")?;
+                }
+                Ok(())
+            }
+            Error::Decompression {
+                offset,
+                stream,
+                reason,
+            } => {
+                write!(f, "stream {stream}: decompression failed")?;
+                if let Some(offset) = offset {
+                    write!(f, " at byte {offset}")?;
+                }
+                write!(f, " ({reason})")
+            }
+            Error::Decryption {
+                offset,
+                stream,
+                reason,
+            } => {
+                write!(f, "stream {stream}: decryption failed")?;
+                if let Some(offset) = offset {
+                    write!(f, " at byte {offset}")?;
+                }
+                write!(f, " ({reason})")
+            }
+            Error::LimitExceeded {
+                offset,
+                stream,
+                limit,
+            } => {
+                write!(f, "at byte {offset}")?;
+                if let Some(stream) = stream {
+                    write!(f, " (stream {stream})")?;
+                }
+                write!(f, ": {limit}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::LimitExceeded { limit, .. } => Some(limit),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Error::Io(value)
+    }
+}
+
+/// for code that only deals in `io::Error`s, the original error can be downcast from the result
+impl From<Error> for io::Error {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::Io(e) => e,
+            Error::EndOfStream => io::ErrorKind::UnexpectedEof.into(),
+            Error::UnsupportedVersion { .. } | Error::UnsupportedFeatures { .. } => {
+                io::Error::new(io::ErrorKind::Unsupported, value)
+            }
+            Error::NoSeekPoints { .. } | Error::NoIndex | Error::NoAttachment { .. } => {
+                io::Error::new(io::ErrorKind::NotFound, value)
+            }
+            _ => io::Error::new(io::ErrorKind::InvalidData, value),
+        }
+    }
+}
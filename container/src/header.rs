@@ -0,0 +1,278 @@
+use std::io::{self, Read, Write};
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use integer_encoding::{VarIntReader, VarIntWriter};
+use litemap::LiteMap;
+
+use crate::{EncodableData, Packet, PacketDataType, side_data::SideData};
+
+/*
+
+Packet header v2 (format 5.x) and v3 (format 6.0 and up):
+    stream : u8
+    flags : u8
+        bit 0: timestamp is absolute, otherwise it's relative to the previous packet
+               (v2: of any stream, v3: of the same stream)
+        bit 1: side data follows
+    data_type : u8
+    packet_idx : varint
+    timestamp : varint (absolute) / zigzag varint (delta)
+    duration : varint
+    [side data, varint lengths (see `side_data`)]
+    data_len : varint
+
+v2 timestamps are in micros, v3 ones in ticks of the stream's `TimeBase`, which differ between streams.
+Absolute timestamps are written at the start of every fragment and at every seek point
+(for v3, on the first packet of every stream after them), so decoding can start at any of them.
+
+*/
+
+const ABSOLUTE_TIMESTAMP: u8 = 1 << 0;
+const HAS_SIDE_DATA: u8 = 1 << 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderVersion {
+    /// fixed size fields, see `Packet`
+    V1,
+    /// varints and timestamp deltas, see above
+    V2,
+    /// v2 with deltas per stream
+    V3,
+}
+
+impl HeaderVersion {
+    pub fn for_format_version(major: u16) -> HeaderVersion {
+        match major {
+            6.. => HeaderVersion::V3,
+            5 => HeaderVersion::V2,
+            _ => HeaderVersion::V1,
+        }
+    }
+
+    // v2 timestamps are relative to whatever came before, they're all kept under one key
+    fn timestamp_key(self, stream: u8) -> u8 {
+        match self {
+            HeaderVersion::V3 => stream,
+            _ => 0,
+        }
+    }
+}
+
+/// Writes and reads packet headers. v2 and v3 headers depend on the previous ones, so this keeps that state around.
+#[derive(Debug, Clone)]
+pub struct HeaderCodec {
+    version: HeaderVersion,
+    last_timestamps: LiteMap<u8, u64>,
+}
+
+impl HeaderCodec {
+    pub fn new(version: HeaderVersion) -> HeaderCodec {
+        HeaderCodec {
+            version,
+            last_timestamps: LiteMap::new(),
+        }
+    }
+
+    pub fn version(&self) -> HeaderVersion {
+        self.version
+    }
+
+    /// forget the previous timestamps: the encoder writes absolute ones next,
+    /// the decoder expects them (after seeking)
+    pub fn reset(&mut self) {
+        self.last_timestamps.clear();
+    }
+
+    /// Continue decoding a packet of `stream` as if a packet with timestamp `previous` had just been read,
+    /// for jumping to a packet without reading the ones before it (see `index`). For v2 headers that's
+    /// the packet stored right before it, for v3 the one of the same stream (see `relative_to_stream`).
+    pub fn resume_after(&mut self, stream: u8, previous: Option<u64>) {
+        self.last_timestamps.clear();
+        if let Some(previous) = previous {
+            self.last_timestamps
+                .insert(self.version.timestamp_key(stream), previous);
+        }
+    }
+
+    /// whether timestamps are relative to the previous packet of the same stream, rather than of any stream
+    pub fn relative_to_stream(&self) -> bool {
+        self.version == HeaderVersion::V3
+    }
+
+    /// returns bytes written
+    pub fn encode_into<W: Write>(&mut self, packet: &Packet, out: &mut W) -> io::Result<u64> {
+        if self.version == HeaderVersion::V1 {
+            return packet.encode_into(out);
+        }
+
+        let timestamp = packet.timestamp;
+        let key = self.version.timestamp_key(packet.stream);
+        let last_timestamp = self.last_timestamps.get(&key).copied();
+
+        let mut flags = 0;
+        if last_timestamp.is_none() {
+            flags |= ABSOLUTE_TIMESTAMP;
+        }
+        if !packet.side_data.is_empty() {
+            flags |= HAS_SIDE_DATA;
+        }
+
+        out.write_u8(packet.stream)?;
+        out.write_u8(flags)?;
+        out.write_u8(packet.data_type as u8)?;
+        let mut total_bytes = 3;
+
+        total_bytes += out.write_varint(packet.packet_idx)?;
+        total_bytes += match last_timestamp {
+            Some(last) => out.write_varint(timestamp as i64 - last as i64)?,
+            None => out.write_varint(timestamp)?,
+        };
+        total_bytes += out.write_varint(packet.duration)?;
+
+        let mut total_bytes = total_bytes as u64;
+        if flags & HAS_SIDE_DATA != 0 {
+            total_bytes += packet.side_data.encode_into(out)?;
+        }
+
+        total_bytes += out.write_varint(packet.data_len)? as u64;
+
+        self.last_timestamps.insert(key, timestamp);
+        Ok(total_bytes)
+    }
+
+    pub fn decode_from<R: Read>(&mut self, input: &mut R) -> io::Result<Packet> {
+        if self.version == HeaderVersion::V1 {
+            return Packet::decode_from(input);
+        }
+
+        let stream = input.read_u8()?;
+        let flags = input.read_u8()?;
+        let data_type = PacketDataType::try_from(input.read_u8()?)?;
+        let packet_idx: u64 = input.read_varint()?;
+        let key = self.version.timestamp_key(stream);
+
+        let timestamp = if flags & ABSOLUTE_TIMESTAMP != 0 {
+            input.read_varint()?
+        } else {
+            let delta: i64 = input.read_varint()?;
+            let last = self.last_timestamps.get(&key).copied().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "packet header: relative timestamp without a previous packet",
+                )
+            })?;
+
+            last.checked_add_signed(delta).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "packet header: timestamp out of range",
+                )
+            })?
+        };
+
+        let duration: u64 = input.read_varint()?;
+        let side_data = if flags & HAS_SIDE_DATA != 0 {
+            SideData::decode_from(input)?
+        } else {
+            SideData::default()
+        };
+        let data_len: u64 = input.read_varint()?;
+
+        self.last_timestamps.insert(key, timestamp);
+
+        Ok(Packet {
+            stream,
+            packet_idx,
+            timestamp,
+            duration,
+            side_data,
+            data_type,
+            data_len,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        Packet, PacketDataType,
+        header::{HeaderCodec, HeaderVersion},
+        side_data,
+    };
+
+    #[test]
+    fn test_v2_roundtrip() {
+        let mut packets = Vec::new();
+        for (idx, (stream, millis)) in [(0, 0), (1, 0), (0, 40), (1, 20), (2, 10), (0, 80)]
+            .into_iter()
+            .enumerate()
+        {
+            let mut packet = Packet::builder()
+                .stream(stream)
+                .timestamp(millis * 1000)
+                .duration(40_000)
+                .build();
+            packet.packet_idx = idx as u64;
+            packet.data_type = PacketDataType::Video;
+            packet.data_len = 1312 * idx as u64;
+            packets.push(packet);
+        }
+
+        packets[2].side_data.set_bool(side_data::KEYFRAME, true);
+
+        for version in [HeaderVersion::V2, HeaderVersion::V3] {
+            let mut encoder = HeaderCodec::new(version);
+            let mut encoded = Vec::new();
+            for (idx, packet) in packets.iter().enumerate() {
+                if idx == 3 {
+                    encoder.reset();
+                }
+
+                encoder.encode_into(packet, &mut encoded).unwrap();
+            }
+
+            let mut decoder = HeaderCodec::new(version);
+            let mut input = encoded.as_slice();
+            for packet in &packets {
+                assert_eq!(&decoder.decode_from(&mut input).unwrap(), packet);
+            }
+
+            assert!(input.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_v2_needs_an_absolute_timestamp() {
+        let packet = Packet::builder()
+            .stream(0)
+            .timestamp(40_000)
+            .duration(40_000)
+            .build();
+
+        let mut encoder = HeaderCodec::new(HeaderVersion::V2);
+        encoder.encode_into(&packet, &mut Vec::new()).unwrap();
+
+        let mut relative = Vec::new();
+        encoder.encode_into(&packet, &mut relative).unwrap();
+
+        let mut decoder = HeaderCodec::new(HeaderVersion::V2);
+        assert!(decoder.decode_from(&mut relative.as_slice()).is_err());
+
+        // v3 deltas are per stream, the timestamp of another one doesn't help
+        let mut encoder = HeaderCodec::new(HeaderVersion::V3);
+        encoder.encode_into(&packet, &mut Vec::new()).unwrap();
+
+        let mut relative = Vec::new();
+        encoder.encode_into(&packet, &mut relative).unwrap();
+
+        let mut decoder = HeaderCodec::new(HeaderVersion::V3);
+        decoder.resume_after(1, Some(0));
+        assert!(decoder.decode_from(&mut relative.as_slice()).is_err());
+
+        decoder.resume_after(0, Some(40_000));
+        assert_eq!(
+            decoder.decode_from(&mut relative.as_slice()).unwrap(),
+            packet
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use std::io::{self, Cursor, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use integer_encoding::{VarIntReader, VarIntWriter};
+
+use crate::{
+    EncodableData,
+    limits::{DecodeLimits, Limit},
+    seek::{delta_decode, delta_encode},
+};
+
+/*
+
+Packet index (optional, format 5.1 and up, trailer layout only), right after the trailer's seek tables:
+    magic : 4 bytes "PIDX"
+    len_elements : u64
+    len_bytes : u64
+    [LZ4, size prepended, one column after the other:
+        stream : u8 each
+        packet_idx : varint each
+        timestamp : delta encoded (ticks of the stream's time base)
+        offset : delta encoded (relative to the first packet, like seek tables)
+        size : varint each (packet header + payload)
+        flags : u8 each
+            bit 0: keyframe
+    ]
+
+One entry per packet, in the order they're stored. Offsets point at the packet header,
+past the sync marker in front of video keyframes.
+
+*/
+
+pub const INDEX_MAGIC: [u8; 4] = *b"PIDX";
+
+const KEYFRAME: u8 = 1 << 0;
+
+// stream and flags, four varints of at most 10 bytes each
+const MAX_ENTRY_LEN: u64 = 2 + 4 * 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub stream: u8,
+    pub packet_idx: u64,
+    /// in ticks of the stream's `TimeBase`
+    pub timestamp: i64,
+    /// where the packet header starts, relative to the first packet
+    pub offset: u64,
+    /// packet header + payload, in bytes
+    pub size: u64,
+    /// decoding can start here: video keyframes and every packet of the other streams
+    pub keyframe: bool,
+}
+
+/// Every packet of a file with its position, for random access without scanning.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PacketIndex {
+    entries: Vec<IndexEntry>,
+}
+
+impl PacketIndex {
+    pub fn new() -> PacketIndex {
+        PacketIndex::default()
+    }
+
+    /// entries have to be pushed in the order the packets are written
+    pub fn push(&mut self, entry: IndexEntry) {
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// position of the entry for packet `packet_idx` of `stream`
+    pub fn find(&self, stream: u8, packet_idx: u64) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.stream == stream && entry.packet_idx == packet_idx)
+    }
+
+    /// decodes what follows the magic, checking the entry count against `limits` before allocating
+    pub fn decode_after_magic<R: Read>(
+        input: &mut R,
+        limits: &DecodeLimits,
+    ) -> io::Result<PacketIndex> {
+        let len_elements = input.read_u64::<LittleEndian>()?;
+        let len_bytes = input.read_u64::<LittleEndian>()?;
+        limits.check(Limit::IndexEntries, len_elements)?;
+
+        // lz4 can grow incompressible data a little
+        let max_len = len_elements.saturating_mul(MAX_ENTRY_LEN);
+        if len_bytes > max_len.saturating_add(max_len / 255 + 16 + 4) {
+            return Err(size_mismatch());
+        }
+
+        if len_elements == 0 {
+            return Ok(PacketIndex::new());
+        }
+
+        let mut compressed = Vec::new();
+        input.take(len_bytes).read_to_end(&mut compressed)?;
+        if compressed.len() as u64 != len_bytes {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        // the decompressed size is prepended, don't let it allocate more than the entries can take
+        if let Some(prefix) = compressed.first_chunk::<4>()
+            && u32::from_le_bytes(*prefix) as u64 > max_len
+        {
+            return Err(size_mismatch());
+        }
+
+        let mut data = Cursor::new(
+            lz4_flex::decompress_size_prepended(&compressed)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        );
+
+        let len = len_elements as usize;
+        let mut streams = vec![0; len];
+        data.read_exact(&mut streams)?;
+        let packet_idxs = (0..len)
+            .map(|_| data.read_varint())
+            .collect::<io::Result<Vec<u64>>>()?;
+        let timestamps = delta_decode(&mut data, len)?;
+        let offsets = delta_decode(&mut data, len)?;
+        let sizes = (0..len)
+            .map(|_| data.read_varint())
+            .collect::<io::Result<Vec<u64>>>()?;
+        let mut flags = vec![0; len];
+        data.read_exact(&mut flags)?;
+
+        let entries = (0..len)
+            .map(|i| IndexEntry {
+                stream: streams[i],
+                packet_idx: packet_idxs[i],
+                timestamp: timestamps[i],
+                offset: offsets[i] as u64,
+                size: sizes[i],
+                keyframe: flags[i] & KEYFRAME != 0,
+            })
+            .collect();
+
+        Ok(PacketIndex { entries })
+    }
+}
+
+fn size_mismatch() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "packet index: size doesn't match the entry count",
+    )
+}
+
+impl EncodableData for PacketIndex {
+    fn estimated_size(&self) -> Option<usize> {
+        Some(INDEX_MAGIC.len() + 16 + self.entries.len() * 8)
+    }
+
+    fn encode_into<W: Write>(&self, out: &mut W) -> io::Result<u64> {
+        out.write_all(&INDEX_MAGIC)?;
+        out.write_u64::<LittleEndian>(self.entries.len() as u64)?;
+
+        if self.entries.is_empty() {
+            out.write_u64::<LittleEndian>(0)?;
+            return Ok(INDEX_MAGIC.len() as u64 + 16);
+        }
+
+        let mut encoded: Vec<u8> = self.entries.iter().map(|entry| entry.stream).collect();
+        for entry in &self.entries {
+            encoded.write_varint(entry.packet_idx)?;
+        }
+        encoded.append(&mut delta_encode(
+            self.entries.iter().map(|entry| entry.timestamp),
+        ));
+        encoded.append(&mut delta_encode(
+            self.entries.iter().map(|entry| entry.offset as i64),
+        ));
+        for entry in &self.entries {
+            encoded.write_varint(entry.size)?;
+        }
+        encoded.extend(
+            self.entries
+                .iter()
+                .map(|entry| if entry.keyframe { KEYFRAME } else { 0 }),
+        );
+
+        let compressed = lz4_flex::compress_prepend_size(&encoded);
+        out.write_u64::<LittleEndian>(compressed.len() as u64)?;
+        out.write_all(&compressed)?;
+
+        Ok((INDEX_MAGIC.len() + 16 + compressed.len()) as u64)
+    }
+
+    fn decode_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+
+        if magic != INDEX_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "packet index: bad magic",
+            ));
+        }
+
+        PacketIndex::decode_after_magic(input, &DecodeLimits::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        EncodableData,
+        index::{IndexEntry, PacketIndex},
+        limits::DecodeLimits,
+    };
+
+    #[test]
+    fn test_index_roundtrip() {
+        let mut index = PacketIndex::new();
+        let mut offset = 0;
+        for i in 0..500u64 {
+            let stream = (i % 3) as u8;
+            let size = 20 + (i * 7919) % 4096;
+            index.push(IndexEntry {
+                stream,
+                packet_idx: i / 3 + 1,
+                timestamp: (i / 3 * 40_000 + stream as u64 * 1312) as i64,
+                offset,
+                size,
+                keyframe: stream == 0 && i % 30 == 0,
+            });
+
+            offset += size;
+        }
+
+        let encoded = index.encode_to_vec();
+        assert_eq!(
+            PacketIndex::decode_from(&mut encoded.as_slice()).unwrap(),
+            index
+        );
+        assert_eq!(index.find(1, 10), Some(28));
+
+        let limits = DecodeLimits {
+            max_index_entries: 499,
+            ..DecodeLimits::default()
+        };
+        assert!(PacketIndex::decode_after_magic(&mut &encoded[4..], &limits).is_err());
+
+        let empty = PacketIndex::new().encode_to_vec();
+        assert!(
+            PacketIndex::decode_from(&mut empty.as_slice())
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
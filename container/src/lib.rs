@@ -10,9 +10,21 @@ use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 use typed_builder::TypedBuilder;
 
-use crate::side_data::SideData;
+pub use crate::error::{Error, Result};
+use crate::{
+    limits::{DecodeLimits, Limit},
+    side_data::SideData,
+};
 
+pub mod cells;
+pub mod demux;
+pub mod encryption;
+pub mod error;
+pub mod header;
+pub mod index;
+pub mod limits;
 pub mod metadata;
+pub mod preamble;
 pub mod seek;
 pub mod side_data;
 
@@ -32,14 +44,67 @@ impl Display for FormatDuration {
 
 File Format!
 
+-- Preamble (see `preamble`)
+    -- (magic: 8 bytes) "ANSI.MOE"
+    -- (major: u16) (minor: u16) format version
+    -- (layout: u8) 0 = trailer, 1 = fragmented
+    -- (trailer_offset: u64) trailer layout only: absolute offset of the trailer, patched in once all packets are written
+
+Trailer layout:
+
+-- Preamble
+-- (interleaved packet data) -> seek table locations are relative to the first packet
+-- Trailer
+    -- (marker: len_bytes, u64) Header: DER-encoded FormatData
+    -- (marker: amount of seektables, u8) Seek Tables, one per stream, video first
+        -- (stream_index: u8)
+        -- (seek_table_length: u64 / bytes)
+    -- Packet index (optional, since 5.1, see `index`): every packet with its offset, size and timestamp
+
+Fragmented layout, for pipes: every fragment can be decoded on its own, so a reader can join at any fragment.
+
+-- Preamble
+-- Fragment (repeated until the end of the stream)
+    -- (marker: len_bytes, u64) Header: DER-encoded FormatData, repeated in every fragment
+    -- (marker: amount of seektables, u8) Seek Tables covering only this fragment, one per stream, video first
+        -- (stream_index: u8)
+        -- (seek_table_length: u64 / bytes)
+    -- (packets_len: u64) bytes of packet data in this fragment -> seek table locations are relative to its first packet
+    -- (interleaved packet data), starting with a video keyframe
+
+Legacy layout (v3.0, still readable): no preamble, the file starts with the header length.
+
 -- (marker: len_bytes, u64) Header: DER-encoded FormatData
 -- (marker: amount of seektables, u8) Seek Tables
     -- (stream_index: u8)
     -- (seek_table_length: u64 / bytes)
 -- (interleaved packet data)
 
+Packet data (all layouts):
+
+-- Packet header, followed by data_len bytes of payload
+    -- v1 (format 4.x and legacy): fixed size, see `Packet`
+    -- v2 (format 5.x): varints and timestamp deltas, see `header`
+    -- v3 (format 6.0 and up): v2 with timestamps in ticks of each stream's `TimeBase` (see `metadata`)
+    -- side data <CRCC> (optional): CRC32C over the v1 encoding of the header without <CRCC> + the payload
+-- Sync marker (8 bytes, `SYNC_MARKER`), in front of every video keyframe since 4.1.
+   Starts with stream index 0xFF, which no stream can use. A reader that hits a damaged packet
+   scans for the next marker instead of giving up on the rest of the file.
+
+Video packets of type `Repeat` (since 6.1) have no payload: the picture stays as it is until the next one.
+
+Attachment streams (since 6.2, see `metadata::AttachmentParameters`) have a single packet of type `Attachment`
+holding the whole file. Those come before every other packet, in the first fragment for fragmented files.
+
+Data streams (since 6.3, see `metadata::DataParameters`) carry one event per packet of type `Data`.
+
+Payloads may be encrypted (since 6.6), see `encryption`.
+
 */
 
+/// Written in front of video keyframes, see the file format above.
+pub const SYNC_MARKER: [u8; 8] = [0xFF, b'S', b'Y', b'N', b'C', 0xA5, 0x5A, 0xFF];
+
 pub trait EncodableData: Sized {
     fn estimated_size(&self) -> Option<usize>;
 
@@ -66,6 +131,12 @@ pub enum PacketDataType {
     Audio = 1,
     Subtitle = 2,
     Unknown = 3,
+    /// a video frame identical to the one before it, without payload (format 6.1 and up)
+    Repeat = 4,
+    /// the whole file of an attachment stream (format 6.2 and up)
+    Attachment = 5,
+    /// an event of a data stream, see `metadata::DataParameters` (format 6.3 and up)
+    Data = 6,
     Invalid = 255,
 }
 
@@ -78,8 +149,16 @@ impl TryFrom<u8> for PacketDataType {
             1 => PacketDataType::Audio,
             2 => PacketDataType::Subtitle,
             3 => PacketDataType::Unknown,
+            4 => PacketDataType::Repeat,
+            5 => PacketDataType::Attachment,
+            6 => PacketDataType::Data,
             255 => PacketDataType::Invalid,
-            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "")),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("packet header: unknown data type {value}"),
+                ));
+            }
         })
     }
 }
@@ -95,8 +174,10 @@ pub struct Packet {
     pub stream: u8,
     #[builder(default, setter(skip))]
     pub packet_idx: u64,
-    pub timestamp: Duration, // micros as u64
-    pub duration: Duration,  // micros as u64
+    /// in ticks of the stream's `metadata::TimeBase`
+    pub timestamp: u64,
+    /// in ticks of the stream's `metadata::TimeBase`
+    pub duration: u64,
     #[builder(default, setter(into))]
     pub side_data: SideData,
     #[builder(default, setter(skip))]
@@ -105,13 +186,69 @@ pub struct Packet {
     pub data_len: u64,
 }
 
+impl Packet {
+    pub fn is_keyframe(&self) -> bool {
+        self.side_data.contains_key(&side_data::KEYFRAME)
+    }
+
+    /// CRC32C over the v1 encoded header (minus any stored checksum) and `data`, whatever the file uses
+    pub fn checksum(&self, data: &[u8]) -> u32 {
+        let mut header = self.clone();
+        header.side_data.remove(&side_data::CHECKSUM);
+
+        let mut encoded = Vec::with_capacity(self.estimated_size().unwrap());
+        header.write_v1(&mut encoded, false).unwrap();
+
+        let crc = crc32c::crc32c(&encoded);
+        crc32c::crc32c_append(crc, data)
+    }
+
+    /// call once the side data and `data_len` are final, anything changed afterwards breaks the checksum
+    pub fn set_checksum(&mut self, data: &[u8]) {
+        let checksum = self.checksum(data);
+        self.side_data
+            .insert(side_data::CHECKSUM, checksum.to_le_bytes().to_vec());
+    }
+
+    /// `None` if the packet doesn't carry a checksum
+    pub fn verify_checksum(&self, data: &[u8]) -> Option<bool> {
+        let stored = self.side_data.get(&side_data::CHECKSUM)?;
+        let stored = u32::from_le_bytes(stored.as_slice().try_into().ok()?);
+
+        Some(stored == self.checksum(data))
+    }
+
+    // `checked`: refuse side data that doesn't fit the v1 layout, instead of truncating its lengths
+    fn write_v1<W: Write>(&self, out: &mut W, checked: bool) -> std::io::Result<u64> {
+        let mut total_bytes = 0u64;
+
+        out.write_u8(self.stream)?;
+        out.write_u64::<LittleEndian>(self.packet_idx)?;
+        out.write_u64::<LittleEndian>(self.timestamp)?;
+        out.write_u64::<LittleEndian>(self.duration)?;
+        total_bytes += 8 * 3 + 1;
+
+        total_bytes += if checked {
+            self.side_data.encode_v1_into(out)?
+        } else {
+            self.side_data.write_v1(out)?
+        };
+
+        out.write_u8(self.data_type as u8)?;
+        out.write_u64::<LittleEndian>(self.data_len)?;
+        total_bytes += 9;
+
+        Ok(total_bytes)
+    }
+}
+
 impl Display for Packet {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "Packet {{")?;
         writeln!(f, "\tstream => {}", self.stream)?;
         writeln!(f, "\tidx => {}", self.packet_idx)?;
-        writeln!(f, "\ttimestamp => {}", FormatDuration(self.timestamp))?;
-        writeln!(f, "\tduration => {}", FormatDuration(self.duration))?;
+        writeln!(f, "\ttimestamp => {} ticks", self.timestamp)?;
+        writeln!(f, "\tduration => {} ticks", self.duration)?;
         writeln!(f, "\tdata_type => {:?}", self.data_type)?;
         writeln!(f, "\tdata_len => {}", self.data_len)?;
         writeln!(f, "\tside_data => {}", self.side_data)?;
@@ -125,28 +262,15 @@ impl EncodableData for Packet {
             1 // stream idx
             + 8 // packet-idx
             + 8 + 8 // timestamp + duration
-            + self.side_data.estimated_size().unwrap()
+            + self.side_data.iter().fold(1, |acc, (_, value)| acc + 4 + 1 + value.len()) // v1 side data
             + 1 // data type
             + 8, // len
         )
     }
 
+    /// the v1 header, see `header` for v2
     fn encode_into<W: Write>(&self, out: &mut W) -> std::io::Result<u64> {
-        let mut total_bytes = 0u64;
-
-        out.write_u8(self.stream)?;
-        out.write_u64::<LittleEndian>(self.packet_idx)?;
-        out.write_u64::<LittleEndian>(self.timestamp.as_micros() as u64)?;
-        out.write_u64::<LittleEndian>(self.duration.as_micros() as u64)?;
-        total_bytes += 8 * 3 + 1;
-
-        total_bytes += self.side_data.encode_into(out)?;
-
-        out.write_u8(self.data_type as u8)?;
-        out.write_u64::<LittleEndian>(self.data_len)?;
-        total_bytes += 9;
-
-        Ok(total_bytes)
+        self.write_v1(out, true)
     }
 
     fn decode_from<R: Read>(input: &mut R) -> std::io::Result<Self> {
@@ -154,15 +278,15 @@ impl EncodableData for Packet {
         let packet_idx = input.read_u64::<LittleEndian>()?;
         let timestamp = input.read_u64::<LittleEndian>()?;
         let duration = input.read_u64::<LittleEndian>()?;
-        let side_data = SideData::decode_from(input)?;
+        let side_data = SideData::decode_v1_from(input)?;
         let data_type = input.read_u8()?;
         let data_len = input.read_u64::<LittleEndian>()?;
 
         Ok(Packet {
             stream,
             packet_idx,
-            timestamp: Duration::from_micros(timestamp),
-            duration: Duration::from_micros(duration),
+            timestamp,
+            duration,
             side_data,
             data_type: PacketDataType::try_from(data_type)?,
             data_len,
@@ -206,22 +330,7 @@ impl EncodableData for SubRect {
     }
 
     fn decode_from<R: Read>(input: &mut R) -> std::io::Result<Self> {
-        let x = input.read_i16::<LittleEndian>()?;
-        let y = input.read_i16::<LittleEndian>()?;
-        let fg = input.read_u8()?;
-        let bg = input.read_u8()?;
-        let text_len = input.read_u32::<LittleEndian>()?;
-        let mut buf = vec![0u8; text_len as usize];
-        input.read_exact(&mut buf)?;
-
-        Ok(SubRect {
-            x,
-            y,
-            fg,
-            bg,
-            text: String::from_utf8(buf)
-                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
-        })
+        SubRect::decode_with_limits(input, &DecodeLimits::default())
     }
 }
 
@@ -235,6 +344,19 @@ impl SubRectVec {
     pub fn into_inner(self) -> Vec<SubRect> {
         self.inner
     }
+
+    pub fn decode_with_limits<R: Read>(input: &mut R, limits: &DecodeLimits) -> io::Result<Self> {
+        let len = limits.check(
+            Limit::SubtitleRects,
+            input.read_u16::<LittleEndian>()? as u64,
+        )?;
+        let mut rects = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            rects.push(SubRect::decode_with_limits(input, limits)?);
+        }
+
+        Ok(SubRectVec { inner: rects })
+    }
 }
 
 impl From<Vec<SubRect>> for SubRectVec {
@@ -261,13 +383,7 @@ impl EncodableData for SubRectVec {
     }
 
     fn decode_from<R: Read>(input: &mut R) -> std::io::Result<Self> {
-        let len = input.read_u16::<LittleEndian>()?;
-        let mut rects = Vec::with_capacity(len as usize);
-        for _ in 0..len {
-            rects.push(SubRect::decode_from(input)?);
-        }
-
-        Ok(SubRectVec { inner: rects })
+        SubRectVec::decode_with_limits(input, &DecodeLimits::default())
     }
 }
 
@@ -276,6 +392,33 @@ impl TypedData for SubRectVec {
 }
 
 impl SubRect {
+    pub fn decode_with_limits<R: Read>(input: &mut R, limits: &DecodeLimits) -> io::Result<Self> {
+        let x = input.read_i16::<LittleEndian>()?;
+        let y = input.read_i16::<LittleEndian>()?;
+        let fg = input.read_u8()?;
+        let bg = input.read_u8()?;
+        let text_len = limits.check(
+            Limit::SubtitleText,
+            input.read_u32::<LittleEndian>()? as u64,
+        )?;
+
+        // the length is checked, but could still be lying about how much is left
+        let mut buf = Vec::new();
+        input.take(text_len).read_to_end(&mut buf)?;
+        if buf.len() as u64 != text_len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        Ok(SubRect {
+            x,
+            y,
+            fg,
+            bg,
+            text: String::from_utf8(buf)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        })
+    }
+
     pub fn to_string(&self) -> String {
         format!(
             "\x1b[{};{}H\x1b[38;5;{}m\x1b[48;5;{}m{}",
@@ -284,3 +427,66 @@ impl SubRect {
     }
     //
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        EncodableData, Packet, SubRect, SubRectVec,
+        limits::{DecodeLimits, Limit, LimitExceeded},
+    };
+
+    #[test]
+    fn test_subtitle_limits() {
+        let subs = SubRectVec::from(vec![SubRect {
+            text: "x".repeat(100),
+            ..Default::default()
+        }]);
+        let encoded = subs.encode_to_vec();
+
+        let limits = DecodeLimits {
+            max_subtitle_text: 99,
+            ..Default::default()
+        };
+        let err = SubRectVec::decode_with_limits(&mut encoded.as_slice(), &limits).unwrap_err();
+        assert_eq!(
+            err.get_ref()
+                .and_then(|e| e.downcast_ref::<LimitExceeded>()),
+            Some(&LimitExceeded {
+                limit: Limit::SubtitleText,
+                value: 100,
+                max: 99
+            })
+        );
+
+        // a length pointing past the end of the data fails without allocating it
+        let mut truncated = encoded.clone();
+        truncated[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(
+            SubRectVec::decode_with_limits(&mut truncated.as_slice(), &DecodeLimits::unlimited())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_checksum_catches_flipped_bits() {
+        let data = b"\x1b[38;5;1mhello";
+        let mut packet = Packet::builder()
+            .stream(0)
+            .timestamp(40_000)
+            .duration(40_000)
+            .build();
+        packet.data_len = data.len() as u64;
+
+        assert_eq!(packet.verify_checksum(data), None);
+
+        packet.set_checksum(data);
+        assert_eq!(packet.verify_checksum(data), Some(true));
+
+        let mut damaged = *data;
+        damaged[3] ^= 0x10;
+        assert_eq!(packet.verify_checksum(&damaged), Some(false));
+
+        packet.data_len += 1;
+        assert_eq!(packet.verify_checksum(data), Some(false));
+    }
+}
@@ -0,0 +1,116 @@
+use std::{fmt::Display, io};
+
+/// Upper bounds for sizes read from a file, checked before anything gets allocated for them.
+/// The defaults are far above anything the encoder writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// DER-encoded `FormatData`, in bytes
+    pub max_header_len: u64,
+    /// packet payload, in bytes
+    pub max_packet_len: u64,
+    /// rects in a single subtitle packet
+    pub max_subtitle_rects: u64,
+    /// text of a single subtitle rect, in bytes
+    pub max_subtitle_text: u64,
+    /// entries in a single seek table
+    pub max_seek_entries: u64,
+    /// entries in the packet index, one per packet
+    pub max_index_entries: u64,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_header_len: 16 << 20,
+            max_packet_len: 256 << 20,
+            max_subtitle_rects: 1024,
+            max_subtitle_text: 64 << 10,
+            max_seek_entries: 16 << 20,
+            max_index_entries: 64 << 20,
+        }
+    }
+}
+
+impl DecodeLimits {
+    /// trusts the file completely
+    pub fn unlimited() -> Self {
+        Self {
+            max_header_len: u64::MAX,
+            max_packet_len: u64::MAX,
+            max_subtitle_rects: u64::MAX,
+            max_subtitle_text: u64::MAX,
+            max_seek_entries: u64::MAX,
+            max_index_entries: u64::MAX,
+        }
+    }
+
+    pub fn max(&self, limit: Limit) -> u64 {
+        match limit {
+            Limit::HeaderLen => self.max_header_len,
+            Limit::PacketLen => self.max_packet_len,
+            Limit::SubtitleRects => self.max_subtitle_rects,
+            Limit::SubtitleText => self.max_subtitle_text,
+            Limit::SeekEntries => self.max_seek_entries,
+            Limit::IndexEntries => self.max_index_entries,
+        }
+    }
+
+    pub fn check(&self, limit: Limit, value: u64) -> Result<u64, LimitExceeded> {
+        let max = self.max(limit);
+        if value > max {
+            return Err(LimitExceeded { limit, value, max });
+        }
+
+        Ok(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    HeaderLen,
+    PacketLen,
+    SubtitleRects,
+    SubtitleText,
+    SeekEntries,
+    IndexEntries,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Limit::HeaderLen => "header length",
+            Limit::PacketLen => "packet length",
+            Limit::SubtitleRects => "subtitle rects",
+            Limit::SubtitleText => "subtitle text length",
+            Limit::SeekEntries => "seek table entries",
+            Limit::IndexEntries => "packet index entries",
+        })
+    }
+}
+
+/// Returned when a file goes over one of the `DecodeLimits`. Readers return it as `Error::LimitExceeded`,
+/// `EncodableData` decoding wrapped in an `io::Error` of kind `InvalidData`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LimitExceeded {
+    pub limit: Limit,
+    pub value: u64,
+    pub max: u64,
+}
+
+impl Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} of {} is over the limit of {}",
+            self.limit, self.value, self.max
+        )
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+impl From<LimitExceeded> for io::Error {
+    fn from(value: LimitExceeded) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, value)
+    }
+}
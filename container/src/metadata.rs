@@ -1,4 +1,4 @@
-use std::{fmt::Display, str::FromStr};
+use std::{fmt::Display, str::FromStr, time::Duration};
 
 use rasn::prelude::*;
 
@@ -9,6 +9,12 @@ pub enum CodecParameters {
     Subtitle(SubtitleParameters),
     #[rasn(tag(explicit(context, 1)))]
     Video(VideoParameters),
+    #[rasn(tag(explicit(context, 2)))]
+    Audio(AudioParameters),
+    #[rasn(tag(explicit(context, 3)))]
+    Attachment(AttachmentParameters),
+    #[rasn(tag(explicit(context, 4)))]
+    Data(DataParameters),
 }
 
 impl CodecParameters {
@@ -26,12 +32,40 @@ impl CodecParameters {
         }
     }
 
+    pub fn is_audio(&self) -> bool {
+        match self {
+            CodecParameters::Audio(_) => true,
+            _ => false,
+        }
+    }
+
     pub fn as_video(&self) -> Option<&VideoParameters> {
         match self {
             CodecParameters::Video(video_parameters) => Some(video_parameters),
             _ => None,
         }
     }
+
+    pub fn as_audio(&self) -> Option<&AudioParameters> {
+        match self {
+            CodecParameters::Audio(audio_parameters) => Some(audio_parameters),
+            _ => None,
+        }
+    }
+
+    pub fn as_attachment(&self) -> Option<&AttachmentParameters> {
+        match self {
+            CodecParameters::Attachment(attachment_parameters) => Some(attachment_parameters),
+            _ => None,
+        }
+    }
+
+    pub fn as_data(&self) -> Option<&DataParameters> {
+        match self {
+            CodecParameters::Data(data_parameters) => Some(data_parameters),
+            _ => None,
+        }
+    }
 }
 
 #[derive(AsnType, Debug, Clone, Decode, Encode, PartialEq, Eq, Hash, Copy)]
@@ -62,6 +96,37 @@ impl Display for ColorMode {
     }
 }
 
+#[derive(AsnType, Debug, Clone, Decode, Encode, PartialEq, Eq, Hash, Copy, Default)]
+#[rasn(enumerated)]
+pub enum VideoCodec {
+    /// pre-rendered escape sequences, written to the terminal as-is
+    #[default]
+    Ansi = 0,
+    /// binary half-block cell grid (see `cells::CellFrame`), turned into escape sequences by the player
+    CellGrid = 1,
+}
+
+impl FromStr for VideoCodec {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "ansi" | "escapes" => VideoCodec::Ansi,
+            "cells" | "cellgrid" | "grid" => VideoCodec::CellGrid,
+            _ => return Err("Invalid video codec!"),
+        })
+    }
+}
+
+impl Display for VideoCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            VideoCodec::Ansi => "ansi",
+            VideoCodec::CellGrid => "cells",
+        })
+    }
+}
+
 #[derive(AsnType, Debug, Clone, Decode, Encode, PartialEq, Eq, Hash, Copy)]
 #[rasn(enumerated)]
 #[repr(u8)]
@@ -102,6 +167,19 @@ pub struct FormatData {
     pub encoder: Utf8String,
     #[rasn(tag(explicit(context, 2)))]
     pub tracks: SequenceOf<Stream>,
+    /// see `features`, absent in files from before feature flags existed
+    #[rasn(tag(explicit(context, 3)))]
+    pub features: Option<u64>,
+    /// sorted by start time
+    #[rasn(tag(explicit(context, 4)))]
+    pub chapters: Option<SequenceOf<Chapter>>,
+    /// see `tags` for the keys the encoder writes
+    #[rasn(tag(explicit(context, 5)))]
+    pub tags: Option<SequenceOf<MetadataTag>>,
+    /// packets are stored in timestamp order across streams, except that one can come up to this many
+    /// microseconds after a packet with a later timestamp. Absent if the encoder didn't keep track
+    #[rasn(identifier = "max-interleave", tag(explicit(context, 6)))]
+    pub max_interleave: Option<u64>,
 }
 
 impl FormatData {
@@ -110,8 +188,143 @@ impl FormatData {
             format_name,
             encoder,
             tracks,
+            features: None,
+            chapters: None,
+            tags: None,
+            max_interleave: None,
         }
     }
+
+    pub fn tags(&self) -> &[MetadataTag] {
+        self.tags.as_deref().unwrap_or_default()
+    }
+
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        find_tag(self.tags(), key)
+    }
+
+    pub fn chapters(&self) -> &[Chapter] {
+        self.chapters.as_deref().unwrap_or_default()
+    }
+
+    /// time base of stream `index`, microseconds for streams that don't exist
+    pub fn time_base(&self, index: u8) -> TimeBase {
+        self.tracks
+            .iter()
+            .find(|stream| stream.index == index)
+            .map_or(TimeBase::MICROS, Stream::time_base)
+    }
+
+    /// the stream with cover art to show before playback, see `AttachmentParameters::is_ansi_still`
+    pub fn ansi_still(&self) -> Option<&Stream> {
+        self.tracks.iter().find(|stream| {
+            stream
+                .parameters
+                .as_attachment()
+                .is_some_and(AttachmentParameters::is_ansi_still)
+        })
+    }
+
+    pub fn features(&self) -> u64 {
+        self.features.unwrap_or(0)
+    }
+
+    /// `max_interleave` as a `Duration`
+    pub fn max_interleave(&self) -> Option<Duration> {
+        self.max_interleave.map(Duration::from_micros)
+    }
+
+    /// the biggest packet of any stream once decompressed, `None` if the encoder didn't record stats for all of them
+    pub fn max_decompressed_size(&self) -> Option<u64> {
+        self.tracks.iter().try_fold(0, |max, track| {
+            Some(max.max(track.stats.as_ref()?.max_decompressed_size))
+        })
+    }
+
+    /// feature bits this version of the crate can't play
+    pub fn unknown_features(&self) -> u64 {
+        self.features() & !features::KNOWN
+    }
+}
+
+/// Bits for `FormatData::features`, one per feature a reader has to understand to play the file.
+pub mod features {
+    /// video packets in between keyframes only carry the cells that changed
+    pub const DELTA_FRAMES: u64 = 1 << 0;
+    /// video is stored as `cells::CellFrame`s
+    pub const CELL_GRID: u64 = 1 << 1;
+    /// packet data contains `SYNC_MARKER`s, which aren't packets
+    pub const SYNC_MARKERS: u64 = 1 << 2;
+    /// the video stream has `PacketDataType::Repeat` packets for frames identical to the one before
+    pub const REPEAT_FRAMES: u64 = 1 << 3;
+    /// packet payloads are encrypted, see `encryption`
+    pub const ENCRYPTED: u64 = 1 << 4;
+
+    pub const KNOWN: u64 = DELTA_FRAMES | CELL_GRID | SYNC_MARKERS | REPEAT_FRAMES | ENCRYPTED;
+}
+
+/// Bits for `Stream::disposition`, taken over from the source. Readers may ignore any of them.
+pub mod disposition {
+    /// the stream to play if the viewer didn't pick one
+    pub const DEFAULT: u64 = 1 << 0;
+    /// subtitles for foreign dialogue, signs and songs only, meant to be shown along with the audio's language
+    pub const FORCED: u64 = 1 << 1;
+    /// meant for the hearing impaired, e.g. subtitles that describe sounds too
+    pub const HEARING_IMPAIRED: u64 = 1 << 2;
+    pub const COMMENTARY: u64 = 1 << 3;
+}
+
+/// Free-form key/value metadata, keys are lowercase.
+#[derive(AsnType, Debug, Clone, Decode, Encode, PartialEq, Eq, Hash)]
+pub struct MetadataTag {
+    #[rasn(tag(explicit(context, 0)))]
+    pub key: Utf8String,
+    #[rasn(tag(explicit(context, 1)))]
+    pub value: Utf8String,
+}
+
+impl MetadataTag {
+    pub fn new(key: impl Into<Utf8String>, value: impl Into<Utf8String>) -> Self {
+        Self {
+            key: key.into(),
+            value: value.into(),
+        }
+    }
+}
+
+fn find_tag<'a>(tags: &'a [MetadataTag], key: &str) -> Option<&'a str> {
+    tags.iter()
+        .find(|tag| tag.key.eq_ignore_ascii_case(key))
+        .map(|tag| tag.value.as_str())
+}
+
+/// Well-known `MetadataTag` keys. Tags copied from the source keep ffmpeg's names.
+pub mod tags {
+    pub const TITLE: &str = "title";
+    pub const SERIES: &str = "series";
+    pub const EPISODE: &str = "episode";
+    pub const DATE: &str = "date";
+    pub const LANGUAGE: &str = "language";
+    /// file name of the source the encoder read
+    pub const SOURCE_FILE: &str = "source-file";
+    /// command line the encoder was run with
+    pub const ENCODE_SETTINGS: &str = "encode-settings";
+}
+
+#[derive(AsnType, Debug, Clone, Decode, Encode, PartialEq, Eq, Hash)]
+pub struct Chapter {
+    #[rasn(tag(explicit(context, 0)))]
+    pub start: u64, // microseconds
+    #[rasn(tag(explicit(context, 1)))]
+    pub end: u64, // microseconds
+    #[rasn(tag(explicit(context, 2)))]
+    pub title: Utf8String,
+}
+
+impl Chapter {
+    pub fn new(start: u64, end: u64, title: Utf8String) -> Self {
+        Self { start, end, title }
+    }
 }
 
 #[derive(AsnType, Debug, Clone, Decode, Encode, PartialEq, Eq, Hash)]
@@ -130,6 +343,17 @@ pub struct Stream {
     pub compression_dict: Option<OctetString>,
     #[rasn(tag(explicit(context, 6)))]
     pub parameters: CodecParameters,
+    #[rasn(tag(explicit(context, 7)))]
+    pub tags: Option<SequenceOf<MetadataTag>>,
+    /// unit of the stream's packet timestamps and seek tables, absent in files from before 6.0 (microseconds)
+    #[rasn(identifier = "time-base", tag(explicit(context, 8)))]
+    pub time_base: Option<TimeBase>,
+    /// absent in files from before 6.4. For fragmented files, of the packets up to the fragment the header is in
+    #[rasn(tag(explicit(context, 9)))]
+    pub stats: Option<StreamStats>,
+    /// see `disposition`, absent in files from before 6.5
+    #[rasn(tag(explicit(context, 10)))]
+    pub disposition: Option<u64>,
 }
 
 impl Stream {
@@ -150,7 +374,141 @@ impl Stream {
             compression_mode,
             compression_dict,
             parameters,
+            tags: None,
+            time_base: None,
+            stats: None,
+            disposition: None,
+        }
+    }
+
+    pub fn tags(&self) -> &[MetadataTag] {
+        self.tags.as_deref().unwrap_or_default()
+    }
+
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        find_tag(self.tags(), key)
+    }
+
+    pub fn time_base(&self) -> TimeBase {
+        self.time_base.unwrap_or(TimeBase::MICROS)
+    }
+
+    pub fn disposition(&self) -> u64 {
+        self.disposition.unwrap_or(0)
+    }
+
+    /// whether all bits of `disposition` are set
+    pub fn has_disposition(&self, disposition: u64) -> bool {
+        self.disposition() & disposition == disposition
+    }
+
+    /// the subtitle language, or the language tag of other streams
+    pub fn language(&self) -> Option<&str> {
+        match &self.parameters {
+            CodecParameters::Subtitle(params) if params.lang != "<unknown>" => Some(&params.lang),
+            _ => self.tag(tags::LANGUAGE),
+        }
+    }
+}
+
+/// Packet sizes of a stream, so readers can size their buffers up front. Sizes are of the packet data,
+/// without the packet headers.
+#[derive(AsnType, Debug, Clone, Copy, Decode, Encode, PartialEq, Eq, Hash, Default)]
+pub struct StreamStats {
+    #[rasn(tag(explicit(context, 0)))]
+    pub frames: u64,
+    #[rasn(identifier = "max-packet-size", tag(explicit(context, 1)))]
+    pub max_packet_size: u64,
+    /// same as `max_packet_size` for uncompressed streams
+    #[rasn(identifier = "max-decompressed-size", tag(explicit(context, 2)))]
+    pub max_decompressed_size: u64,
+    #[rasn(identifier = "total-bytes", tag(explicit(context, 3)))]
+    pub total_bytes: u64,
+}
+
+impl StreamStats {
+    /// counts a packet of `size` bytes that decompresses to `decompressed_size`
+    pub fn add(&mut self, size: u64, decompressed_size: u64) {
+        self.frames += 1;
+        self.max_packet_size = self.max_packet_size.max(size);
+        self.max_decompressed_size = self.max_decompressed_size.max(decompressed_size);
+        self.total_bytes += size;
+    }
+
+    pub fn avg_packet_size(&self) -> u64 {
+        self.total_bytes.checked_div(self.frames).unwrap_or(0)
+    }
+}
+
+/// Timestamps are counted in ticks of `num / den` seconds, e.g. 1001/24000 for 23.976 fps video
+/// or 1/48000 for audio. Conversions round to the nearest tick (or nanosecond),
+/// always from the absolute tick count, so rounding errors don't add up over a long stream.
+#[derive(AsnType, Debug, Clone, Copy, Decode, Encode, PartialEq, Eq, Hash)]
+pub struct TimeBase {
+    #[rasn(tag(explicit(context, 0)))]
+    pub num: u32,
+    #[rasn(tag(explicit(context, 1)))]
+    pub den: u32,
+}
+
+impl TimeBase {
+    pub const MICROS: TimeBase = TimeBase::new(1, 1_000_000);
+    pub const NANOS: TimeBase = TimeBase::new(1, 1_000_000_000);
+
+    /// panics if either part is zero
+    pub const fn new(num: u32, den: u32) -> TimeBase {
+        assert!(num != 0 && den != 0, "time base can't be zero");
+        TimeBase { num, den }
+    }
+
+    /// zero in either part, which a file could still contain
+    pub fn is_valid(&self) -> bool {
+        self.num != 0 && self.den != 0
+    }
+
+    /// `ticks` of this time base in ticks of `to`, saturating
+    pub fn rescale(self, ticks: i64, to: TimeBase) -> i64 {
+        if self == to {
+            return ticks;
         }
+
+        let mul = self.num as i128 * to.den as i128;
+        let div = self.den as i128 * to.num as i128;
+
+        // round half away from zero
+        let scaled = ticks as i128 * mul;
+        let rounded = (scaled + scaled.signum() * div / 2) / div;
+        rounded.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+    }
+
+    pub fn to_micros(self, ticks: i64) -> i64 {
+        self.rescale(ticks, TimeBase::MICROS)
+    }
+
+    pub fn from_micros(self, micros: i64) -> i64 {
+        TimeBase::MICROS.rescale(micros, self)
+    }
+
+    pub fn to_duration(self, ticks: u64) -> Duration {
+        let nanos = self.rescale(ticks.min(i64::MAX as u64) as i64, TimeBase::NANOS) as u64;
+        Duration::from_nanos(nanos)
+    }
+
+    pub fn from_duration(self, duration: Duration) -> u64 {
+        let nanos = duration.as_nanos().min(i64::MAX as u128) as i64;
+        TimeBase::NANOS.rescale(nanos, self) as u64
+    }
+}
+
+impl Default for TimeBase {
+    fn default() -> Self {
+        TimeBase::MICROS
+    }
+}
+
+impl Display for TimeBase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.num, self.den)
     }
 }
 
@@ -190,6 +548,12 @@ pub struct VideoParameters {
     pub height: u16,
     #[rasn(tag(explicit(context, 2)))]
     pub color: ColorMode,
+    /// maximum distance between keyframes, in frames. if absent, every packet is a keyframe.
+    #[rasn(identifier = "keyframe-interval", tag(explicit(context, 3)))]
+    pub keyframe_interval: Option<u32>,
+    /// absent in files that predate the codec field, which are always `VideoCodec::Ansi`
+    #[rasn(tag(explicit(context, 4)))]
+    pub codec: Option<VideoCodec>,
 }
 
 impl VideoParameters {
@@ -198,6 +562,155 @@ impl VideoParameters {
             width,
             height,
             color,
+            keyframe_interval: None,
+            codec: None,
         }
     }
+
+    pub fn codec(&self) -> VideoCodec {
+        self.codec.unwrap_or_default()
+    }
+
+    pub fn uses_delta_frames(&self) -> bool {
+        self.keyframe_interval.is_some_and(|interval| interval > 1)
+    }
+}
+
+#[derive(AsnType, Debug, Clone, Decode, Encode, PartialEq, Eq, Hash, Copy)]
+#[rasn(enumerated)]
+pub enum AudioCodec {
+    /// interleaved signed 16-bit little endian samples
+    Pcm = 0,
+}
+
+impl FromStr for AudioCodec {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "pcm" | "raw" | "s16le" => AudioCodec::Pcm,
+            _ => return Err("Invalid audio codec!"),
+        })
+    }
+}
+
+impl Display for AudioCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AudioCodec::Pcm => "pcm",
+        })
+    }
+}
+
+#[derive(AsnType, Debug, Clone, Decode, Encode, PartialEq, Eq, Hash)]
+pub struct AudioParameters {
+    #[rasn(identifier = "sample-rate", tag(explicit(context, 0)))]
+    pub sample_rate: u32,
+    #[rasn(value("0..=255"), tag(explicit(context, 1)))]
+    pub channels: u8,
+    #[rasn(tag(explicit(context, 2)))]
+    pub codec: AudioCodec,
+}
+
+impl AudioParameters {
+    pub fn new(sample_rate: u32, channels: u8, codec: AudioCodec) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            codec,
+        }
+    }
+}
+
+/// mime type of cover art rendered as escape sequences, at the size of the video
+pub const ANSI_STILL_MIME: &str = "text/x-ansi";
+
+/// A file carried along with the streams (cover art, fonts, ...), not tied to any point in time.
+/// The stream holds a single `PacketDataType::Attachment` packet with the whole file.
+#[derive(AsnType, Debug, Clone, Decode, Encode, PartialEq, Eq, Hash)]
+pub struct AttachmentParameters {
+    #[rasn(identifier = "mime-type", tag(explicit(context, 0)))]
+    pub mime_type: Utf8String,
+    #[rasn(tag(explicit(context, 1)))]
+    pub filename: Utf8String,
+    /// cover art, either the source picture or its rendering (`ANSI_STILL_MIME`)
+    #[rasn(tag(explicit(context, 2)))]
+    pub cover: Option<bool>,
+}
+
+impl AttachmentParameters {
+    pub fn new(mime_type: Utf8String, filename: Utf8String) -> Self {
+        Self {
+            mime_type,
+            filename,
+            cover: None,
+        }
+    }
+
+    pub fn is_cover(&self) -> bool {
+        self.cover.unwrap_or(false)
+    }
+
+    /// cover art that can be written to the terminal as it is
+    pub fn is_ansi_still(&self) -> bool {
+        self.is_cover() && self.mime_type == ANSI_STILL_MIME
+    }
+}
+
+#[derive(AsnType, Debug, Clone, Decode, Encode, PartialEq, Eq, Hash, Copy)]
+#[rasn(enumerated)]
+pub enum DataEncoding {
+    /// bytes only the application knows what to make of
+    Opaque = 0,
+    Utf8 = 1,
+}
+
+/// Timed events that aren't shown (scene labels, song titles, sponsor segments, ...), for applications
+/// to react to. Every `PacketDataType::Data` packet carries one event, lasting for its duration.
+#[derive(AsnType, Debug, Clone, Decode, Encode, PartialEq, Eq, Hash)]
+pub struct DataParameters {
+    #[rasn(tag(explicit(context, 0)))]
+    pub encoding: DataEncoding,
+    /// what the payloads hold, e.g. `application/json`
+    #[rasn(identifier = "mime-type", tag(explicit(context, 1)))]
+    pub mime_type: Option<Utf8String>,
+}
+
+impl DataParameters {
+    pub fn new(encoding: DataEncoding) -> Self {
+        Self {
+            encoding,
+            mime_type: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::metadata::TimeBase;
+
+    #[test]
+    fn test_time_base() {
+        let ntsc = TimeBase::new(1001, 24000);
+
+        // a 24 minute episode, converted frame by frame, lands right on the frame
+        let frames = 24 * 60 * 24000 / 1001;
+        assert_eq!(
+            ntsc.to_duration(frames),
+            Duration::from_nanos(frames * 1001 * 1_000_000_000 / 24000)
+        );
+        assert_eq!(ntsc.from_duration(ntsc.to_duration(frames)), frames);
+        assert_eq!(
+            ntsc.from_micros(ntsc.to_micros(frames as i64)),
+            frames as i64
+        );
+
+        assert_eq!(ntsc.to_micros(1), 41_708);
+        assert_eq!(ntsc.to_micros(-1), -41_708);
+        assert_eq!(TimeBase::new(1, 48_000).rescale(1000, ntsc), 0);
+        assert_eq!(TimeBase::new(1, 48_000).rescale(48_000, ntsc), 24);
+        assert_eq!(TimeBase::MICROS.to_micros(1312), 1312);
+    }
 }
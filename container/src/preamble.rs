@@ -0,0 +1,147 @@
+use std::io::{self, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{EncodableData, Error};
+
+pub const MAGIC: [u8; 8] = *b"ANSI.MOE";
+
+/// bumped for changes older readers can't cope with, these get rejected
+pub const VERSION_MAJOR: u16 = 6;
+/// bumped for every other change to the wire format
+pub const VERSION_MINOR: u16 = 6;
+/// oldest major version this crate still reads
+pub const OLDEST_MAJOR: u16 = 4;
+
+/// where the trailer offset sits in the file, so the encoder can patch it in once it knows it
+pub const TRAILER_OFFSET_POSITION: u64 = 8 + 2 + 2 + 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// packets first, header + seek tables at the end
+    Trailer { trailer_offset: u64 },
+    /// self-contained fragments, for pipes
+    Fragmented,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Preamble {
+    pub major: u16,
+    pub minor: u16,
+    pub layout: Layout,
+}
+
+impl Preamble {
+    pub fn new(layout: Layout) -> Preamble {
+        Preamble {
+            major: VERSION_MAJOR,
+            minor: VERSION_MINOR,
+            layout,
+        }
+    }
+
+    /// decodes what follows the magic, rejecting major versions this crate doesn't know
+    pub fn decode_after_magic<R: Read>(input: &mut R) -> crate::Result<Preamble> {
+        let major = input.read_u16::<LittleEndian>()?;
+        let minor = input.read_u16::<LittleEndian>()?;
+
+        if !(OLDEST_MAJOR..=VERSION_MAJOR).contains(&major) {
+            return Err(Error::UnsupportedVersion { major, minor });
+        }
+
+        let layout = match input.read_u8()? {
+            0 => Layout::Trailer {
+                trailer_offset: input.read_u64::<LittleEndian>()?,
+            },
+            1 => Layout::Fragmented,
+            layout => {
+                return Err(Error::HeaderDecode {
+                    offset: TRAILER_OFFSET_POSITION - 1,
+                    reason: format!("preamble: unknown layout {layout}"),
+                });
+            }
+        };
+
+        Ok(Preamble {
+            major,
+            minor,
+            layout,
+        })
+    }
+}
+
+impl EncodableData for Preamble {
+    fn estimated_size(&self) -> Option<usize> {
+        Some(match self.layout {
+            Layout::Trailer { .. } => TRAILER_OFFSET_POSITION as usize + 8,
+            Layout::Fragmented => TRAILER_OFFSET_POSITION as usize,
+        })
+    }
+
+    fn encode_into<W: Write>(&self, out: &mut W) -> io::Result<u64> {
+        out.write_all(&MAGIC)?;
+        out.write_u16::<LittleEndian>(self.major)?;
+        out.write_u16::<LittleEndian>(self.minor)?;
+
+        match self.layout {
+            Layout::Trailer { trailer_offset } => {
+                out.write_u8(0)?;
+                out.write_u64::<LittleEndian>(trailer_offset)?;
+            }
+            Layout::Fragmented => out.write_u8(1)?,
+        }
+
+        Ok(self.estimated_size().unwrap() as u64)
+    }
+
+    fn decode_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic)?;
+
+        if magic != MAGIC {
+            return Err(Error::BadMagic.into());
+        }
+
+        Ok(Preamble::decode_after_magic(input)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        EncodableData, Error,
+        preamble::{Layout, MAGIC, Preamble, TRAILER_OFFSET_POSITION, VERSION_MAJOR},
+    };
+
+    #[test]
+    fn test_roundtrip() {
+        let preamble = Preamble::new(Layout::Trailer {
+            trailer_offset: 1312,
+        });
+
+        let encoded = preamble.encode_to_vec();
+        assert_eq!(encoded.len(), TRAILER_OFFSET_POSITION as usize + 8);
+        assert_eq!(
+            &encoded[TRAILER_OFFSET_POSITION as usize..],
+            &1312u64.to_le_bytes()
+        );
+
+        let decoded = Preamble::decode_from(&mut encoded.as_slice()).unwrap();
+        assert_eq!(decoded, preamble);
+    }
+
+    #[test]
+    fn test_rejects_foreign_files() {
+        let mut newer = Preamble::new(Layout::Fragmented);
+        newer.major = VERSION_MAJOR + 1;
+
+        let encoded = newer.encode_to_vec();
+        assert!(Preamble::decode_from(&mut encoded.as_slice()).is_err());
+        assert!(matches!(
+            Preamble::decode_after_magic(&mut &encoded[MAGIC.len()..]),
+            Err(Error::UnsupportedVersion { major, .. }) if major == VERSION_MAJOR + 1
+        ));
+
+        assert!(Preamble::decode_from(&mut b"\x7fELF\x02\x01\x01\x00\x00".as_slice()).is_err());
+    }
+}
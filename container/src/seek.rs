@@ -1,7 +1,9 @@
-use std::io::{self, Read};
+use std::io::{self, Cursor, Read};
 
 use integer_encoding::{VarIntReader, VarIntWriter};
 
+use crate::limits::{DecodeLimits, Limit};
+
 /*
 
 Seek table format:
@@ -13,6 +15,7 @@ Seek table format:
 
 #[derive(PartialEq, PartialOrd, Copy, Clone, Debug)]
 pub struct SeekEntry {
+    /// in ticks of the stream's `TimeBase`
     pub ts: i64,
     pub location: i64,
 }
@@ -40,16 +43,21 @@ pub fn delta_encode(mut iter: impl Iterator<Item = i64>) -> Vec<u8> {
 
 pub fn delta_decode(input: &mut impl Read, len: usize) -> io::Result<Vec<i64>> {
     let mut prev_val: i64 = input.read_varint()?;
-    let mut prev_delta = 0;
+    let mut prev_delta: i64 = 0;
 
     let mut out = Vec::new();
     out.push(prev_val);
 
-    for _ in 0..(len - 1) {
+    // hostile tables can make the sums overflow
+    let out_of_range = || io::Error::new(io::ErrorKind::InvalidData, "delta out of range");
+
+    for _ in 0..len.saturating_sub(1) {
         let delta_of_delta: i64 = input.read_varint()?;
-        prev_delta += delta_of_delta;
+        prev_delta = prev_delta
+            .checked_add(delta_of_delta)
+            .ok_or_else(out_of_range)?;
 
-        prev_val += prev_delta;
+        prev_val = prev_val.checked_add(prev_delta).ok_or_else(out_of_range)?;
 
         out.push(prev_val);
     }
@@ -57,8 +65,61 @@ pub fn delta_decode(input: &mut impl Read, len: usize) -> io::Result<Vec<i64>> {
     Ok(out)
 }
 
+// two varints per entry, at most 10 bytes each
+fn max_decoded_len(len_elements: u64) -> u64 {
+    len_elements.saturating_mul(20)
+}
+
+/// Checks the sizes in a seek table's header, before reading what follows it.
+pub fn check_seektable_size(
+    len_bytes: u64,
+    len_elements: u64,
+    limits: &DecodeLimits,
+) -> io::Result<()> {
+    limits.check(Limit::SeekEntries, len_elements)?;
+
+    // lz4 can grow incompressible data a little
+    let max_len = max_decoded_len(len_elements);
+    if len_elements == 0 || len_bytes > max_len.saturating_add(max_len / 255 + 16 + 4) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "size doesn't match the entry count",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Decodes the `len_bytes` of LZ4 data following a seek table's header, see `check_seektable_size`.
+pub fn decode_seektable(compressed: &[u8], len_elements: u64) -> io::Result<Vec<SeekEntry>> {
+    // the decompressed size is prepended, don't let it allocate more than the entries can take
+    if let Some(prefix) = compressed.first_chunk::<4>()
+        && u32::from_le_bytes(*prefix) as u64 > max_decoded_len(len_elements)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "size doesn't match the entry count",
+        ));
+    }
+
+    let mut data = Cursor::new(
+        lz4_flex::decompress_size_prepended(compressed)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+    );
+
+    let timestamps = delta_decode(&mut data, len_elements as usize)?;
+    let locations = delta_decode(&mut data, len_elements as usize)?;
+
+    Ok(timestamps
+        .into_iter()
+        .zip(locations)
+        .map(|(ts, location)| SeekEntry { ts, location })
+        .collect())
+}
+
 #[cfg(test)]
 mod test {
+    use integer_encoding::VarIntWriter;
     use tinyrand::{RandRange, StdRand};
 
     use crate::seek::{delta_decode, delta_encode};
@@ -91,4 +152,13 @@ mod test {
 
         assert_eq!(decoded, input);
     }
+
+    #[test]
+    fn test_delta_overflow() {
+        let mut encoded = Vec::new();
+        encoded.write_varint(i64::MAX).unwrap();
+        encoded.write_varint(1i64).unwrap();
+
+        assert!(delta_decode(&mut encoded.as_slice(), 2).is_err());
+    }
 }
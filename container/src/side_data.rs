@@ -1,59 +1,139 @@
 use std::{
-    io::{Read, Write},
+    io::{self, Read, Write},
     ops::{Deref, DerefMut},
 };
 
-use arrayvec::ArrayVec;
 use byteorder::{ReadBytesExt, WriteBytesExt};
+use integer_encoding::{VarInt, VarIntReader, VarIntWriter};
 use litemap::LiteMap;
 
 use crate::EncodableData;
 
 use std::fmt::Display;
 
+/*
+
+Side data format:
+    v2 and v3 headers (format 5.0 and up):
+        entries : varint
+        [tag : 4 bytes, len : varint, value : len bytes]
+    v1 headers:
+        entries : u8
+        [tag : 4 bytes, len : u8, value : len bytes]
+
+Tag registry, all-uppercase tags are reserved for this crate:
+    CMPM  COMPRESSION_METHOD  u8, `metadata::CompressionMode` the payload was compressed with
+    DCLE  DECOMPRESSED_LEN    u64, payload length before compression
+    KEYF  KEYFRAME            bool, decodable without previous packets of the stream
+    CRCC  CHECKSUM            u32 LE, see `Packet::checksum`
+    NONC  NONCE               24 bytes, the payload is encrypted (see `encryption`)
+    KYID  KEY_ID              which key the payload is encrypted with, see `encryption`
+
+Anything else (e.g. `Tag::new(*b"clip")`) is free for tools to use, readers ignore tags they don't know.
+
+*/
+
 #[derive(PartialEq, PartialOrd, Eq, Ord, Clone, Copy, Debug)]
 pub struct Tag {
     inner: [u8; 4],
 }
 
 impl Tag {
-    const unsafe fn new_unchecked(tag: [u8; 4]) -> Tag {
+    /// panics unless all four bytes are printable ascii, at compile time when used in a const
+    pub const fn new(tag: [u8; 4]) -> Tag {
+        let mut i = 0;
+        while i < tag.len() {
+            assert!(tag[i].is_ascii_graphic(), "tags have to be printable ascii");
+            i += 1;
+        }
+
         Tag { inner: tag }
     }
 
-    fn new_checked(tag: [u8; 4]) -> Tag {
-        for val in tag {
-            assert!(val.is_ascii_graphic());
-        }
+    pub fn as_bytes(&self) -> &[u8; 4] {
+        &self.inner
+    }
 
-        Tag { inner: tag }
+    /// tags made only of uppercase letters and digits belong to this crate, see the registry above
+    pub fn is_reserved(&self) -> bool {
+        self.inner
+            .iter()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
     }
 }
 
 impl Display for Tag {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<{}>", std::str::from_utf8(&self.inner).unwrap())
+        write!(f, "<{}>", self.inner.escape_ascii())
     }
 }
 
-pub const COMPRESSION_METHOD: Tag = unsafe { Tag::new_unchecked([b'C', b'M', b'P', b'M']) };
-pub const DECOMPRESSED_LEN: Tag = unsafe { Tag::new_unchecked([b'D', b'C', b'L', b'E']) };
+pub const COMPRESSION_METHOD: Tag = Tag::new(*b"CMPM");
+pub const DECOMPRESSED_LEN: Tag = Tag::new(*b"DCLE");
+/// present on packets that can be decoded without any previous packet of the stream
+pub const KEYFRAME: Tag = Tag::new(*b"KEYF");
+/// CRC32C of the packet header (without this entry) and its payload, as u32 LE
+pub const CHECKSUM: Tag = Tag::new(*b"CRCC");
+pub const NONCE: Tag = Tag::new(*b"NONC");
+pub const KEY_ID: Tag = Tag::new(*b"KYID");
 
 #[repr(transparent)]
 #[derive(Default, Debug, PartialEq, Clone)]
 pub struct SideData {
-    inner: LiteMap<Tag, ArrayVec<u8, 256>>,
+    inner: LiteMap<Tag, Vec<u8>>,
 }
 
-impl EncodableData for SideData {
-    fn estimated_size(&self) -> Option<usize> {
-        Some(self.inner.iter().fold(
-            1, /* length marker */
-            |acc, e| acc + 4 /* tag */ + 1 /* length marker */ + e.1.len(),
-        ))
+impl SideData {
+    /// u64 values are stored as 8 bytes LE
+    pub fn get_u64(&self, tag: &Tag) -> Option<u64> {
+        let value = self.inner.get(tag)?;
+        Some(u64::from_le_bytes(value.as_slice().try_into().ok()?))
     }
 
-    fn encode_into<W: Write>(&self, out: &mut W) -> std::io::Result<u64> {
+    pub fn set_u64(&mut self, tag: Tag, value: u64) {
+        self.inner.insert(tag, value.to_le_bytes().to_vec());
+    }
+
+    /// bools are a single byte, anything but 0 is true
+    pub fn get_bool(&self, tag: &Tag) -> Option<bool> {
+        match self.inner.get(tag)?.as_slice() {
+            [value] => Some(*value != 0),
+            _ => None,
+        }
+    }
+
+    pub fn set_bool(&mut self, tag: Tag, value: bool) {
+        self.inner.insert(tag, vec![value as u8]);
+    }
+
+    /// `None` if the tag is missing or isn't utf-8
+    pub fn get_str(&self, tag: &Tag) -> Option<&str> {
+        std::str::from_utf8(self.inner.get(tag)?).ok()
+    }
+
+    pub fn set_str(&mut self, tag: Tag, value: &str) {
+        self.inner.insert(tag, value.as_bytes().to_vec());
+    }
+
+    /// the v1 header format, which has no room for more than 255 entries or values over 255 bytes
+    pub fn encode_v1_into<W: Write>(&self, out: &mut W) -> io::Result<u64> {
+        if self.inner.len() > u8::MAX as usize
+            || self
+                .inner
+                .values()
+                .any(|value| value.len() > u8::MAX as usize)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "side data: too large for a v1 packet header",
+            ));
+        }
+
+        self.write_v1(out)
+    }
+
+    // lengths get truncated, so only for checksums: they're computed over the v1 layout in every version
+    pub(crate) fn write_v1<W: Write>(&self, out: &mut W) -> io::Result<u64> {
         let mut total_bytes: u64 = 0;
 
         out.write_u8(self.inner.len() as u8)?;
@@ -71,28 +151,73 @@ impl EncodableData for SideData {
         Ok(total_bytes)
     }
 
-    fn decode_from<R: Read>(input: &mut R) -> std::io::Result<Self> {
+    pub fn decode_v1_from<R: Read>(input: &mut R) -> io::Result<Self> {
         let len = input.read_u8()?;
 
         let mut data = LiteMap::with_capacity(len as usize);
-
         for _ in 0..len {
-            let mut tag: [u8; 4] = [0; 4];
-            input.read_exact(&mut tag)?;
+            let tag = read_tag(input)?;
+            let value_len = input.read_u8()?;
 
-            let marker = input.read_u8()?;
+            data.insert(tag, read_value(input, value_len as u64)?);
+        }
 
-            let mut buf = ArrayVec::new();
+        Ok(SideData::from(data))
+    }
+}
 
-            // SAFETY: capacity is guaranteed to be 256, equal to max data that can be read since marker: u8
-            let slice =
-                unsafe { std::slice::from_raw_parts_mut(buf.as_mut_ptr(), marker as usize) };
-            input.read_exact(slice)?;
+fn read_tag<R: Read>(input: &mut R) -> io::Result<Tag> {
+    let mut tag: [u8; 4] = [0; 4];
+    input.read_exact(&mut tag)?;
 
-            // SET initialized elements to = <marker> elements read
-            unsafe { buf.set_len(marker as usize) };
+    // not validated, files may carry tags from tools we don't know about
+    Ok(Tag { inner: tag })
+}
+
+fn read_value<R: Read>(input: &mut R, len: u64) -> io::Result<Vec<u8>> {
+    // read_to_end instead of a preallocated buffer, a damaged length shouldn't allocate gigabytes
+    let mut value = Vec::new();
+    input.take(len).read_to_end(&mut value)?;
+
+    if value.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(value)
+}
+
+impl EncodableData for SideData {
+    fn estimated_size(&self) -> Option<usize> {
+        Some(self.inner.iter().fold(
+            self.inner.len().required_space(), /* length marker */
+            |acc, e| acc + 4 /* tag */ + e.1.len().required_space() + e.1.len(),
+        ))
+    }
+
+    fn encode_into<W: Write>(&self, out: &mut W) -> std::io::Result<u64> {
+        let mut total_bytes = out.write_varint(self.inner.len())? as u64;
+
+        for (key, val) in self.inner.iter() {
+            out.write_all(&key.inner)?;
+            total_bytes += 4;
+            total_bytes += out.write_varint(val.len())? as u64;
+
+            out.write_all(val)?;
+            total_bytes += val.len() as u64;
+        }
+
+        Ok(total_bytes)
+    }
+
+    fn decode_from<R: Read>(input: &mut R) -> std::io::Result<Self> {
+        let len: u64 = input.read_varint()?;
+
+        let mut data = LiteMap::new();
+        for _ in 0..len {
+            let tag = read_tag(input)?;
+            let value_len: u64 = input.read_varint()?;
 
-            data.insert(unsafe { Tag::new_unchecked(tag) }, buf);
+            data.insert(tag, read_value(input, value_len)?);
         }
 
         Ok(SideData::from(data))
@@ -109,14 +234,14 @@ impl Display for SideData {
     }
 }
 
-impl From<LiteMap<Tag, ArrayVec<u8, 256>>> for SideData {
-    fn from(value: LiteMap<Tag, ArrayVec<u8, 256>>) -> Self {
+impl From<LiteMap<Tag, Vec<u8>>> for SideData {
+    fn from(value: LiteMap<Tag, Vec<u8>>) -> Self {
         Self { inner: value }
     }
 }
 
 impl Deref for SideData {
-    type Target = LiteMap<Tag, ArrayVec<u8, 256>>;
+    type Target = LiteMap<Tag, Vec<u8>>;
 
     fn deref(&self) -> &Self::Target {
         &self.inner
@@ -128,3 +253,33 @@ impl DerefMut for SideData {
         &mut self.inner
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        EncodableData,
+        side_data::{DECOMPRESSED_LEN, KEYFRAME, SideData, Tag},
+    };
+
+    #[test]
+    fn test_large_values_roundtrip() {
+        const NOTE: Tag = Tag::new(*b"note");
+
+        let mut side_data = SideData::default();
+        side_data.set_u64(DECOMPRESSED_LEN, 1312);
+        side_data.set_bool(KEYFRAME, true);
+        side_data.set_str(NOTE, &"yachiyo ".repeat(100));
+
+        let encoded = side_data.encode_to_vec();
+        assert_eq!(encoded.len(), side_data.estimated_size().unwrap());
+
+        let decoded = SideData::decode_from(&mut encoded.as_slice()).unwrap();
+        assert_eq!(decoded, side_data);
+        assert_eq!(decoded.get_u64(&DECOMPRESSED_LEN), Some(1312));
+        assert_eq!(decoded.get_bool(&KEYFRAME), Some(true));
+        assert_eq!(decoded.get_str(&NOTE).map(str::len), Some(800));
+
+        assert!(!NOTE.is_reserved());
+        assert!(side_data.encode_v1_into(&mut Vec::new()).is_err());
+    }
+}
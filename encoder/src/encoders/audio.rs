@@ -0,0 +1,22 @@
+use container::PacketDataType;
+
+use crate::{encoders::FFToAnsi, ff::packet::FFPacket};
+
+/// stores decoded audio as-is (interleaved s16le, see `AudioCodec::Pcm`)
+pub struct PcmAudioEncoder;
+
+impl FFToAnsi for PcmAudioEncoder {
+    fn process(
+        &mut self,
+        input: &FFPacket,
+        packet: &mut container::Packet,
+        data: &mut Vec<u8>,
+    ) -> std::io::Result<()> {
+        data.extend_from_slice(&input.binary_data);
+
+        packet.data_len = data.len() as u64;
+        packet.data_type = PacketDataType::Audio;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    time::Duration,
+};
+
+use container::{
+    PacketDataType,
+    metadata::{DataEncoding, DataParameters, TimeBase},
+};
+use serde::Deserialize;
+
+use crate::{
+    encoders::FFToAnsi,
+    ff::packet::{FFPacket, PacketType},
+};
+
+/// One line of a sidecar file. Times are in seconds, the payload is exactly one of
+/// `text` (stored as is), `json` (stored serialized) or `bytes` (opaque).
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SidecarEvent {
+    start: f64,
+    #[serde(default)]
+    duration: f64,
+    text: Option<String>,
+    json: Option<serde_json::Value>,
+    bytes: Option<Vec<u8>>,
+}
+
+struct Event {
+    start: Duration,
+    duration: Duration,
+    payload: Vec<u8>,
+}
+
+/// Timed events for a data stream, read from a sidecar file with one JSON object per line (see `SidecarEvent`).
+/// They're handed out as packets in microseconds, alongside the decoded packets around them in time.
+pub struct SidecarSource {
+    stream_idx: usize,
+    frame_index: usize,
+    events: VecDeque<Event>,
+    parameters: DataParameters,
+}
+
+impl SidecarSource {
+    pub fn open(path: impl AsRef<Path>, stream_idx: usize) -> anyhow::Result<SidecarSource> {
+        let path = path.as_ref();
+        let mut events = Vec::new();
+        let (mut all_text, mut all_json) = (true, true);
+
+        for (line_idx, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let context = || format!("{}, line {}", path.display(), line_idx + 1);
+            let event: SidecarEvent =
+                serde_json::from_str(&line).map_err(|e| anyhow::anyhow!("{}: {e}", context()))?;
+
+            let payload = match (event.text, event.json, event.bytes) {
+                (Some(text), None, None) => {
+                    all_json = false;
+                    text.into_bytes()
+                }
+                (None, Some(json), None) => serde_json::to_vec(&json)?,
+                (None, None, Some(bytes)) => {
+                    (all_text, all_json) = (false, false);
+                    bytes
+                }
+                _ => anyhow::bail!("{}: needs one of text, json or bytes", context()),
+            };
+
+            events.push(Event {
+                start: Duration::try_from_secs_f64(event.start)
+                    .map_err(|e| anyhow::anyhow!("{}: start: {e}", context()))?,
+                duration: Duration::try_from_secs_f64(event.duration)
+                    .map_err(|e| anyhow::anyhow!("{}: duration: {e}", context()))?,
+                payload,
+            });
+        }
+
+        events.sort_by_key(|event| event.start);
+
+        let parameters = DataParameters {
+            encoding: if all_text {
+                DataEncoding::Utf8
+            } else {
+                DataEncoding::Opaque
+            },
+            mime_type: (all_json && !events.is_empty()).then(|| "application/json".to_string()),
+        };
+
+        Ok(SidecarSource {
+            stream_idx,
+            frame_index: 0,
+            events: events.into(),
+            parameters,
+        })
+    }
+
+    pub fn stream_index(&self) -> usize {
+        self.stream_idx
+    }
+
+    pub fn parameters(&self) -> &DataParameters {
+        &self.parameters
+    }
+
+    pub fn time_base(&self) -> TimeBase {
+        TimeBase::MICROS
+    }
+
+    /// the next event starting at or before `time`
+    pub fn next_until(&mut self, time: Duration) -> Option<FFPacket> {
+        if self.events.front()?.start > time {
+            return None;
+        }
+
+        let event = self.events.pop_front()?;
+        let packet = FFPacket {
+            stream_idx: self.stream_idx,
+            frame_idx: self.frame_index,
+            kind: PacketType::Data,
+            timestamp: event.start.as_micros() as u64,
+            duration: event.duration.as_micros() as u64,
+            time_base: self.time_base(),
+            binary_data: event.payload,
+            ..Default::default()
+        };
+        self.frame_index += 1;
+
+        Some(packet)
+    }
+}
+
+/// stores event payloads as they are
+pub struct DataEncoder;
+
+impl FFToAnsi for DataEncoder {
+    fn process(
+        &mut self,
+        input: &FFPacket,
+        packet: &mut container::Packet,
+        data: &mut Vec<u8>,
+    ) -> std::io::Result<()> {
+        data.extend_from_slice(&input.binary_data);
+
+        packet.data_len = data.len() as u64;
+        packet.data_type = PacketDataType::Data;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+use std::io;
+
+use chacha20poly1305::{
+    XChaCha20Poly1305,
+    aead::{AeadCore, AeadInPlace, KeyInit, OsRng},
+};
+use container::{
+    encryption::{self, KEY_LEN, TAG_LEN},
+    side_data,
+};
+
+/// Seals payloads with XChaCha20-Poly1305, see `container::encryption`. Goes after compression,
+/// encrypted data doesn't compress, and after the packet index is set: the whole header is authenticated.
+/// Not a `PostProcessor` on purpose, no pipeline step may run after it.
+pub struct Encryptor {
+    cipher: XChaCha20Poly1305,
+    key_id: Option<Vec<u8>>,
+}
+
+impl Encryptor {
+    /// `key_id` lets players holding several keys pick the right one
+    pub fn new(key: &[u8; KEY_LEN], key_id: Option<Vec<u8>>) -> Self {
+        Encryptor {
+            cipher: XChaCha20Poly1305::new(key.into()),
+            key_id,
+        }
+    }
+
+    /// the last change to the packet before it's written, only the checksum may be added after it
+    pub fn seal(&mut self, packet: &mut container::Packet, data: &mut Vec<u8>) -> io::Result<()> {
+        // random nonces are safe to use with the same key for as many packets as anyone will ever encode
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+        // everything in the associated data has to be final before sealing
+        if let Some(key_id) = &self.key_id {
+            packet.side_data.insert(side_data::KEY_ID, key_id.clone());
+        }
+        packet.data_len = (data.len() + TAG_LEN) as u64;
+
+        self.cipher
+            .encrypt_in_place(&nonce, &encryption::associated_data(packet), data)
+            .map_err(|_| io::Error::other("encryption failed"))?;
+
+        packet.side_data.insert(side_data::NONCE, nonce.to_vec());
+
+        Ok(())
+    }
+}
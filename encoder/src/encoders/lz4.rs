@@ -1,6 +1,5 @@
 use std::io::{self};
 
-use arrayvec::ArrayVec;
 use container::{metadata::CompressionMode, side_data};
 use lz4_flex::{
     block::{compress_into_with_dict, get_maximum_output_size},
@@ -42,13 +41,12 @@ impl PostProcessor for Lz4Compressor {
 
         self.scratch.truncate(compressed_data_len);
 
-        packet.side_data.insert(
-            side_data::DECOMPRESSED_LEN,
-            ArrayVec::from_iter((uncompressed_len as u64).to_le_bytes()),
-        );
+        packet
+            .side_data
+            .set_u64(side_data::DECOMPRESSED_LEN, uncompressed_len as u64);
         packet.side_data.insert(
             side_data::COMPRESSION_METHOD,
-            ArrayVec::from_iter([CompressionMode::Lz4 as u8]),
+            vec![CompressionMode::Lz4 as u8],
         );
 
         packet.data_len = compressed_data_len as u64;
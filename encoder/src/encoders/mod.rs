@@ -2,11 +2,15 @@ use crate::ff::packet::FFPacket;
 use arrayvec::ArrayVec;
 use byteorder::{LittleEndian, WriteBytesExt};
 use container::{
-    Packet as AnsiPacket,
+    Packet as AnsiPacket, PacketDataType,
+    metadata::TimeBase,
     seek::{SeekEntry, delta_encode},
 };
 use tsz_compress::prelude::TszCompressV2;
 
+pub mod audio;
+pub mod data;
+pub mod encrypt;
 pub mod lz4;
 pub mod subtitles;
 pub mod video;
@@ -50,6 +54,12 @@ impl Pipeline {
         data: &mut Vec<u8>,
     ) -> std::io::Result<()> {
         self.start.process(input, packet, data)?;
+
+        // repeats have no payload to compress
+        if packet.data_type == PacketDataType::Repeat {
+            return Ok(());
+        }
+
         for step in self.post_steps.iter_mut() {
             step.post_process(packet, data)?;
         }
@@ -60,16 +70,19 @@ impl Pipeline {
 
 pub struct SeekTableEncoder {
     stream_index: u8,
-    resolution: u64,    // every n millis,
-    last_recorded: u64, // n millis
+    time_base: TimeBase, // of the stream, entries are stored in it
+    resolution: u64,     // every n millis,
+    last_recorded: u64,  // n millis
     entries: Vec<SeekEntry>,
 }
 
 impl SeekTableEncoder {
-    pub fn new(stream_index: u8) -> Self {
+    /// records at most one seek point every `resolution` milliseconds, 0 records all of them
+    pub fn new(stream_index: u8, time_base: TimeBase, resolution: u64) -> Self {
         Self {
             stream_index,
-            resolution: 100,
+            time_base,
+            resolution,
             last_recorded: Default::default(),
             entries: Vec::with_capacity(32_000),
         }
@@ -78,27 +91,48 @@ impl SeekTableEncoder {
     pub fn set_stream_index(&mut self, stream_index: u8) {
         self.stream_index = stream_index;
     }
+
+    pub fn stream_index(&self) -> u8 {
+        self.stream_index
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
 }
 
 impl SeekTableEncoder {
-    pub fn ingest(&mut self, packet: &AnsiPacket, position: u64) {
-        if packet.timestamp.as_millis() == 0
-            || packet.timestamp.as_millis() as u64 - self.last_recorded >= self.resolution
+    /// returns whether the packet became a seek point
+    pub fn ingest(&mut self, packet: &AnsiPacket, position: u64) -> bool {
+        // seeking has to land on something we can start decoding from, only video has delta and repeat packets
+        if packet.stream != self.stream_index
+            || packet.data_type == PacketDataType::Repeat
+            || (packet.data_type == PacketDataType::Video && !packet.is_keyframe())
         {
+            return false;
+        }
+
+        let millis = self.time_base.to_duration(packet.timestamp).as_millis() as u64;
+        if self.entries.is_empty() || millis.saturating_sub(self.last_recorded) >= self.resolution {
             self.entries.push(SeekEntry {
-                ts: packet.timestamp.as_micros() as i64,
+                ts: packet.timestamp as i64,
                 location: position as i64,
             });
-            self.last_recorded = packet.timestamp.as_millis() as u64;
+            self.last_recorded = millis;
+            return true;
         }
+
+        false
     }
 
-    pub fn finish(self) -> Vec<u8> {
+    /// encodes the recorded entries and starts over with an empty table
+    pub fn finish(&mut self) -> Vec<u8> {
         let mut out = Vec::new();
 
+        self.last_recorded = 0;
         let (timestamps, locations): (Vec<i64>, Vec<i64>) = self
             .entries
-            .into_iter()
+            .drain(..)
             .map(|SeekEntry { ts, location }| (ts, location))
             .unzip();
 
@@ -4,9 +4,13 @@ use colorful::{
     palette::{AnsiColorMap, CAM02},
     pattern_dithering::{MatrixSize, PatternDither},
 };
-use container::{EncodableData, Packet as AnsiPacket, PacketDataType, metadata::ColorMode};
-use image::{ImageBuffer, Rgb, imageops};
-use img2ansi::AnsiFrame;
+use container::{
+    EncodableData, Packet as AnsiPacket, PacketDataType,
+    metadata::{ColorMode, VideoCodec},
+    side_data,
+};
+use image::{GrayImage, ImageBuffer, Rgb, RgbImage, imageops};
+use img2ansi::{ToAnsi, ToCells};
 
 use crate::{encoders::FFToAnsi, ff::packet::FFPacket};
 
@@ -19,6 +23,7 @@ pub enum DitherMethod {
 }
 
 pub struct AnsiVideoEncoder {
+    pub codec: VideoCodec,
     pub color_mode: ColorMode,
     pub dither_mode: DitherMethod,
     pub matrix_size: MatrixSize,
@@ -26,6 +31,131 @@ pub struct AnsiVideoEncoder {
     pub width: i64,
     pub height: i64,
     pub blue_noise: Option<Bluenoise>,
+    pub frames: FrameHistory,
+}
+
+/// Keeps the previously encoded frame around so that non-key packets only need to carry the cells that changed.
+#[derive(Default)]
+pub struct FrameHistory {
+    keyframes: KeyframeState,
+    rgb: Option<RgbImage>,
+    indexed: Option<GrayImage>,
+    cells: Option<Vec<u8>>,
+}
+
+/// what ended up in a video packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameKind {
+    Keyframe,
+    Delta,
+    /// same as the frame before, nothing written
+    Repeat,
+}
+
+impl FrameHistory {
+    /// `keyframe_interval` is the maximum distance between keyframes, in frames. 0 or 1 disables delta frames.
+    pub fn new(keyframe_interval: u64) -> Self {
+        FrameHistory {
+            keyframes: KeyframeState {
+                interval: keyframe_interval,
+                ..Default::default()
+            },
+            rgb: None,
+            indexed: None,
+            cells: None,
+        }
+    }
+
+    /// Frames identical to the one before become `PacketDataType::Repeat` packets. They count towards
+    /// the keyframe interval like deltas, so they're off too when delta frames are.
+    pub fn with_repeats(mut self, repeats: bool) -> Self {
+        self.keyframes.repeats = repeats;
+        self
+    }
+
+    fn encode_rgb(&mut self, image: RgbImage, data: &mut Vec<u8>) -> std::io::Result<FrameKind> {
+        self.keyframes.encode(&mut self.rgb, image, data)
+    }
+
+    fn encode_indexed(
+        &mut self,
+        image: GrayImage,
+        data: &mut Vec<u8>,
+    ) -> std::io::Result<FrameKind> {
+        self.keyframes.encode(&mut self.indexed, image, data)
+    }
+
+    /// cell grids are always full frames, only repeats are left out
+    fn encode_cells(&mut self, cells: Vec<u8>, data: &mut Vec<u8>) -> FrameKind {
+        if self.keyframes.repeats_previous(self.cells.as_ref(), &cells) {
+            return FrameKind::Repeat;
+        }
+
+        data.extend_from_slice(&cells);
+        self.keyframes.since_keyframe = 0;
+        self.cells = Some(cells);
+
+        FrameKind::Keyframe
+    }
+}
+
+#[derive(Default)]
+struct KeyframeState {
+    interval: u64,
+    repeats: bool,
+    since_keyframe: u64,
+    last_keyframe_len: usize,
+}
+
+impl KeyframeState {
+    // counts the frame if it can be left out
+    fn repeats_previous<I: PartialEq>(&mut self, previous: Option<&I>, image: &I) -> bool {
+        if self.repeats
+            && self.since_keyframe + 1 < self.interval
+            && previous.is_some_and(|previous| previous == image)
+        {
+            self.since_keyframe += 1;
+            return true;
+        }
+
+        false
+    }
+
+    fn encode<I: ToAnsi + PartialEq>(
+        &mut self,
+        previous: &mut Option<I>,
+        image: I,
+        data: &mut Vec<u8>,
+    ) -> std::io::Result<FrameKind> {
+        if self.repeats_previous(previous.as_ref(), &image) {
+            return Ok(FrameKind::Repeat);
+        }
+
+        let start = data.len();
+
+        if let Some(previous_image) = previous.as_ref()
+            && self.since_keyframe + 1 < self.interval
+        {
+            image.to_ansi_delta(previous_image, data)?;
+
+            // a delta that's bigger than a full frame isn't worth it
+            if data.len() - start <= self.last_keyframe_len {
+                self.since_keyframe += 1;
+                *previous = Some(image);
+                return Ok(FrameKind::Delta);
+            }
+
+            data.truncate(start);
+        }
+
+        image.to_ansi(data)?;
+
+        self.since_keyframe = 0;
+        self.last_keyframe_len = data.len() - start;
+        *previous = Some(image);
+
+        Ok(FrameKind::Keyframe)
+    }
 }
 
 impl FFToAnsi for AnsiVideoEncoder {
@@ -44,31 +174,66 @@ impl FFToAnsi for AnsiVideoEncoder {
 
         data.reserve((self.width * self.height * 20) as usize);
 
-        match self.color_mode {
+        // cell grids are always full frames, the player diffs them itself
+        let kind = match self.color_mode {
             ColorMode::Full => {
-                AnsiFrame::from(image).encode_into(data)?;
+                let owned =
+                    RgbImage::from_raw(image.width(), image.height(), image.to_vec()).unwrap();
+
+                match self.codec {
+                    VideoCodec::Ansi => self.frames.encode_rgb(owned, data)?,
+                    VideoCodec::CellGrid => self
+                        .frames
+                        .encode_cells(owned.to_cells().encode_to_vec(), data),
+                }
+            }
+            ColorMode::EightBit => {
+                let indexed = match self.dither_mode {
+                    DitherMethod::FloydSteinberg => self.floyd_steinberg(image),
+                    DitherMethod::Pattern => self.pattern_dither(image),
+                    DitherMethod::None => self.index_image(image),
+                    DitherMethod::BlueNoise => self.blue_noise(image),
+                };
+
+                match self.codec {
+                    VideoCodec::Ansi => self.frames.encode_indexed(indexed, data)?,
+                    VideoCodec::CellGrid => self
+                        .frames
+                        .encode_cells(indexed.to_cells().encode_to_vec(), data),
+                }
             }
-            ColorMode::EightBit => match self.dither_mode {
-                DitherMethod::FloydSteinberg => self.floyd_steinberg(image, data)?,
-                DitherMethod::Pattern => self.pattern_dither(image, data)?,
-                DitherMethod::None => self.index_image(image, data)?,
-                DitherMethod::BlueNoise => self.blue_noise(image, data)?,
-            },
         };
 
+        if kind == FrameKind::Keyframe {
+            packet.side_data.set_bool(side_data::KEYFRAME, true);
+        }
+
         packet.data_len = data.len() as u64;
-        packet.data_type = PacketDataType::Video;
+        packet.data_type = match kind {
+            FrameKind::Repeat => PacketDataType::Repeat,
+            _ => PacketDataType::Video,
+        };
 
         Ok(())
     }
 }
 
+/// a picture shown on its own (cover art), as a full frame of escape sequences
+pub fn render_still(image: &RgbImage, color_mode: ColorMode) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    match color_mode {
+        ColorMode::Full => image.to_ansi(&mut data)?,
+        ColorMode::EightBit => {
+            imageops::index_colors(image, &const { AnsiColorMap::<CAM02>::new() })
+                .to_ansi(&mut data)?
+        }
+    }
+
+    Ok(data)
+}
+
 impl AnsiVideoEncoder {
-    fn floyd_steinberg(
-        &mut self,
-        in_image: ImageBuffer<Rgb<u8>, &[u8]>,
-        data: &mut Vec<u8>,
-    ) -> std::io::Result<()> {
+    fn floyd_steinberg(&mut self, in_image: ImageBuffer<Rgb<u8>, &[u8]>) -> GrayImage {
         let mut base_image =
             ImageBuffer::from_vec(self.width as u32, self.height as u32, in_image.to_vec())
                 .unwrap();
@@ -80,52 +245,26 @@ impl AnsiVideoEncoder {
             *idx = image::Luma([(AnsiColorMap::<CAM02>::reverse_lookup(&pixel.0)).unwrap()]);
         }
 
-        AnsiFrame::from(indexed_image).encode_into(data)?;
-
-        Ok(())
+        indexed_image
     }
 
-    fn pattern_dither(
-        &mut self,
-        in_image: ImageBuffer<Rgb<u8>, &[u8]>,
-        data: &mut Vec<u8>,
-    ) -> std::io::Result<()> {
-        let indexed = in_image.pattern_dither(
+    fn pattern_dither(&mut self, in_image: ImageBuffer<Rgb<u8>, &[u8]>) -> GrayImage {
+        in_image.pattern_dither(
             self.matrix_size,
             self.multiplier,
             const { AnsiColorMap::<CAM02>::new() },
-        );
-
-        AnsiFrame::from(indexed).encode_into(data)?;
-
-        Ok(())
+        )
     }
 
-    fn blue_noise(
-        &mut self,
-        in_image: ImageBuffer<Rgb<u8>, &[u8]>,
-        data: &mut Vec<u8>,
-    ) -> std::io::Result<()> {
-        let indexed = self.blue_noise.as_ref().unwrap().dither(&in_image);
-
-        AnsiFrame::from(indexed).encode_into(data)?;
-
-        Ok(())
+    fn blue_noise(&mut self, in_image: ImageBuffer<Rgb<u8>, &[u8]>) -> GrayImage {
+        self.blue_noise.as_ref().unwrap().dither(&in_image)
     }
 
-    fn index_image(
-        &mut self,
-        in_image: ImageBuffer<Rgb<u8>, &[u8]>,
-        data: &mut Vec<u8>,
-    ) -> std::io::Result<()> {
+    fn index_image(&mut self, in_image: ImageBuffer<Rgb<u8>, &[u8]>) -> GrayImage {
         let base_image =
             ImageBuffer::from_vec(self.width as u32, self.height as u32, in_image.to_vec())
                 .unwrap();
 
-        let indexed_image =
-            imageops::index_colors(&base_image, &const { AnsiColorMap::<CAM02>::new() });
-        AnsiFrame::from(indexed_image).encode_into(data)?;
-
-        Ok(())
+        imageops::index_colors(&base_image, &const { AnsiColorMap::<CAM02>::new() })
     }
 }
@@ -1,6 +1,5 @@
 use std::io::{self};
 
-use arrayvec::ArrayVec;
 use container::{metadata::CompressionMode, side_data};
 use zstd::{bulk::Compressor, zstd_safe};
 
@@ -44,13 +43,12 @@ impl PostProcessor for ZstdCompressor {
             .compress_to_buffer(data, &mut self.scratch)?;
         self.scratch.truncate(compressed_data_len);
 
-        packet.side_data.insert(
-            side_data::DECOMPRESSED_LEN,
-            ArrayVec::from_iter((uncompressed_len as u64).to_le_bytes()),
-        );
+        packet
+            .side_data
+            .set_u64(side_data::DECOMPRESSED_LEN, uncompressed_len as u64);
         packet.side_data.insert(
             side_data::COMPRESSION_METHOD,
-            ArrayVec::from_iter([CompressionMode::Zstd as u8]),
+            vec![CompressionMode::Zstd as u8],
         );
 
         packet.data_len = compressed_data_len as u64;
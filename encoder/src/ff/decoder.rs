@@ -1,25 +1,31 @@
 use std::collections::HashMap;
 use std::time::Duration;
 
+use container::metadata::{Chapter, TimeBase, disposition};
 use ffmpeg::format::context::common::StreamIter;
+use ffmpeg::format::stream::Disposition;
 use ffmpeg::format::{Pixel, input as ff_input};
 use ffmpeg_the_third::codec::Id as CodecID;
 use ffmpeg_the_third::codec::context::Context as CodecContext;
+use ffmpeg_the_third::codec::decoder::Audio as AudioDecoder;
 use ffmpeg_the_third::codec::decoder::Video as VideoDecoder;
 use ffmpeg_the_third::codec::decoder::subtitle::Subtitle as FFSubtitleDecoder;
 use ffmpeg_the_third::codec::subtitle::Subtitle as FFSubtitleFrame;
 use ffmpeg_the_third::ffi::{AV_TIME_BASE, av_frame_unref};
 use ffmpeg_the_third::format::context::Input as InputContext;
+use ffmpeg_the_third::format::{Sample, sample::Type as SampleType};
+use ffmpeg_the_third::software::resampling::Context as ResamplerContext;
 use ffmpeg_the_third::software::scaling::{Context as ScalerContext, flag::Flags as ScalerFlags};
-use ffmpeg_the_third::util::frame::Video as VideoFrame;
+use ffmpeg_the_third::util::frame::{Audio as AudioFrame, Video as VideoFrame};
 use ffmpeg_the_third::{self as ffmpeg, media::Type as StreamType};
-use ffmpeg_the_third::{Rational, Rescale, Stream};
+use ffmpeg_the_third::{ChannelLayout, Rational, Rescale, Stream};
+use image::RgbImage;
 use litemap::LiteMap;
 use thingbuf::{mpsc::blocking as channel, recycling::WithCapacity}; // this is gory man
 
-use super::MICROSECOND_TIMEBASE;
 use super::packet::FFPacket;
 use super::subtitles::{ASSDecoder, SubtitleDecoder};
+use super::{MICROSECOND_TIMEBASE, to_time_base};
 
 struct DecoderScratch {
     decoded: VideoFrame,
@@ -50,15 +56,151 @@ impl DecoderScratch {
 pub struct FFDecoder {
     input_ctx: Option<InputContext>,
     video: VideoProcessor,
+    pub audio: Option<AudioProcessor>,
+    /// the best audio stream and why it couldn't be decoded, if it's left out of `audio`
+    pub skipped_audio: Option<(usize, anyhow::Error)>,
     pub subs: LiteMap<usize, SubtitleProcessor>,
+    pub attachments: Vec<Attachment>,
     packet_tx: channel::Sender<FFPacket, WithCapacity>,
 }
 
+// codec extradata, where matroska keeps attachments and ass subtitles their header
+fn extradata(context: &CodecContext) -> Vec<u8> {
+    let codec = unsafe { context.as_ptr().as_ref().unwrap() };
+
+    if codec.extradata_size <= 0 {
+        return Vec::new();
+    }
+
+    let mut data_buf = vec![0; codec.extradata_size as usize];
+    unsafe {
+        std::ptr::copy(
+            codec.extradata,
+            data_buf.as_mut_ptr(),
+            codec.extradata_size as usize,
+        )
+    };
+
+    data_buf
+}
+
+/// A file from the source that isn't played: a matroska attachment (fonts, ...) or an attached picture (cover art)
+pub struct Attachment {
+    pub stream_index: usize,
+    pub filename: String,
+    pub mime_type: String,
+    pub data: Vec<u8>,
+    /// attached pictures, decoded and scaled to the size of the video
+    pub picture: Option<RgbImage>,
+    pub metadata: HashMap<String, String>,
+}
+
+impl Attachment {
+    fn from_stream(stream: Stream<'_>, target_x: i64, target_y: i64) -> Option<Attachment> {
+        let stream_index = stream.index();
+        let metadata: HashMap<String, String> = stream
+            .metadata()
+            .iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect();
+
+        if stream.parameters().medium() == StreamType::Attachment {
+            let context = CodecContext::from_parameters(stream.parameters()).ok()?;
+
+            return Some(Attachment {
+                stream_index,
+                filename: metadata
+                    .get("filename")
+                    .cloned()
+                    .unwrap_or_else(|| format!("attachment-{stream_index}")),
+                mime_type: metadata
+                    .get("mimetype")
+                    .cloned()
+                    .unwrap_or_else(|| "application/octet-stream".to_string()),
+                data: extradata(&context),
+                picture: None,
+                metadata,
+            });
+        }
+
+        if !stream.disposition().contains(Disposition::ATTACHED_PIC) {
+            return None;
+        }
+
+        // the picture is a single packet kept on the stream, it never comes up while demuxing
+        let data = unsafe {
+            let packet = &(*stream.as_ptr()).attached_pic;
+            if packet.data.is_null() || packet.size <= 0 {
+                return None;
+            }
+
+            std::slice::from_raw_parts(packet.data, packet.size as usize).to_vec()
+        };
+
+        let (mime_type, extension) = match stream.parameters().id() {
+            CodecID::MJPEG => ("image/jpeg", "jpg"),
+            CodecID::PNG => ("image/png", "png"),
+            CodecID::WEBP => ("image/webp", "webp"),
+            _ => ("application/octet-stream", "bin"),
+        };
+
+        Some(Attachment {
+            stream_index,
+            filename: metadata
+                .get("filename")
+                .cloned()
+                .unwrap_or_else(|| format!("cover.{extension}")),
+            mime_type: mime_type.to_string(),
+            picture: decode_picture(&stream, &data, target_x, target_y).ok(),
+            data,
+            metadata,
+        })
+    }
+}
+
+fn decode_picture(
+    stream: &Stream<'_>,
+    data: &[u8],
+    target_x: i64,
+    target_y: i64,
+) -> anyhow::Result<RgbImage> {
+    let mut decoder = CodecContext::from_parameters(stream.parameters())?
+        .decoder()
+        .video()?;
+    decoder.send_packet(&ffmpeg::Packet::copy(data))?;
+    decoder.send_eof()?;
+
+    let mut decoded = VideoFrame::empty();
+    decoder.receive_frame(&mut decoded)?;
+
+    let mut scaler = ScalerContext::get(
+        decoded.format(),
+        decoded.width(),
+        decoded.height(),
+        Pixel::RGB24,
+        target_x as u32,
+        target_y as u32,
+        ScalerFlags::BILINEAR,
+    )?;
+
+    let mut scaled = VideoFrame::empty();
+    scaler.run(&decoded, &mut scaled)?;
+
+    let len = target_x as usize * target_y as usize * 3;
+    RgbImage::from_raw(
+        target_x as u32,
+        target_y as u32,
+        scaled.data(0)[..len].to_vec(),
+    )
+    .ok_or_else(|| anyhow::anyhow!("attached picture of stream {} is cut off", stream.index()))
+}
+
 pub struct SubtitleProcessor {
     metadata: HashMap<String, String>,
     ff: FFSubtitleDecoder,
     transformer: Box<dyn SubtitleDecoder>,
     sub_index: usize,
+    time_base: TimeBase,
     frame_index: usize,
 }
 
@@ -71,8 +213,15 @@ impl SubtitleProcessor {
         &self.metadata
     }
 
+    /// the source stream's, packets keep their timestamps
+    pub fn time_base(&self) -> TimeBase {
+        self.time_base
+    }
+
     fn from_stream(sub_stream: Stream<'_>, target_x: i64, target_y: i64) -> anyhow::Result<Self> {
         let sub_index = sub_stream.index();
+        let time_base = to_time_base(sub_stream.time_base())
+            .ok_or_else(|| anyhow::anyhow!("subtitle stream {sub_index} has no time base"))?;
         let metadata = sub_stream
             .metadata()
             .iter()
@@ -80,25 +229,7 @@ impl SubtitleProcessor {
             .collect();
 
         let sub_decoder_context = CodecContext::from_parameters(sub_stream.parameters())?;
-
-        let sub_data = 'block: {
-            let subtitle_codec = unsafe { sub_decoder_context.as_ptr().as_ref().unwrap() };
-
-            if subtitle_codec.extradata_size <= 0 {
-                break 'block String::new();
-            }
-
-            let mut data_buf = vec![0; subtitle_codec.extradata_size as usize];
-            unsafe {
-                std::ptr::copy(
-                    subtitle_codec.extradata,
-                    data_buf.as_mut_ptr(),
-                    subtitle_codec.extradata_size as usize,
-                )
-            };
-
-            String::from_utf8_lossy(&data_buf).into_owned()
-        };
+        let sub_data = String::from_utf8_lossy(&extradata(&sub_decoder_context)).into_owned();
 
         let ssa_decoder = ASSDecoder::create(&sub_data, target_x, target_y);
         let sub_decoder = sub_decoder_context.decoder().subtitle()?;
@@ -108,6 +239,7 @@ impl SubtitleProcessor {
             transformer: Box::new(ssa_decoder),
             metadata,
             sub_index,
+            time_base,
             frame_index: 0,
         })
     }
@@ -119,7 +251,7 @@ impl SubtitleProcessor {
         tx: &channel::Sender<FFPacket, WithCapacity>,
     ) -> anyhow::Result<()> {
         let mut slot = tx.send_ref()?;
-        slot.ingest_packet(stream, self.frame_index, false, packet);
+        slot.ingest_packet(stream, self.frame_index, self.time_base, false, packet);
 
         let mut out = FFSubtitleFrame::new();
         let _ = self.ff.decode(packet, &mut out)?;
@@ -138,6 +270,7 @@ impl SubtitleProcessor {
 
 struct VideoProcessor {
     video_stream_idx: usize,
+    time_base: TimeBase,
     decoder: VideoDecoder,
     scaler: ScalerContext,
     frame_index: usize,
@@ -147,6 +280,8 @@ struct VideoProcessor {
 impl VideoProcessor {
     fn from_stream(video_stream: Stream<'_>, target_x: i64, target_y: i64) -> anyhow::Result<Self> {
         let index = video_stream.index();
+        let time_base = to_time_base(video_stream.time_base())
+            .ok_or_else(|| anyhow::anyhow!("video stream {index} has no time base"))?;
 
         let mut decoder_ctx = CodecContext::from_parameters(video_stream.parameters())?;
         if let Ok(parallelism) = std::thread::available_parallelism() {
@@ -169,6 +304,7 @@ impl VideoProcessor {
 
         Ok(VideoProcessor {
             video_stream_idx: index,
+            time_base,
             decoder,
             scaler,
             frame_index: 0,
@@ -186,14 +322,20 @@ impl VideoProcessor {
         let mut decoded = 0;
 
         while self.decoder.receive_frame(decode_buf).is_ok() {
+            // a frame without any timestamp can't be placed, one before the start goes at the start
+            let Some(pts) = decode_buf.pts().or_else(|| decode_buf.timestamp()) else {
+                continue;
+            };
+
             self.scaler.run(decode_buf, scaled_buf)?;
 
             let mut packet_slot = tx.send_ref()?;
             packet_slot.ingest_video(
                 stream,
                 self.frame_index,
-                decode_buf.pts().unwrap() as u64,
-                decode_buf.packet().duration as u64,
+                self.time_base,
+                pts.max(0) as u64,
+                decode_buf.packet().duration.max(0) as u64,
                 scaled_buf,
             );
             self.frame_index += 1;
@@ -208,11 +350,141 @@ impl VideoProcessor {
     }
 }
 
+/// output format for decoded audio; samples are always resampled to packed s16le
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioFormat {
+    pub sample_rate: u32,
+    pub channels: u8,
+}
+
+pub struct AudioProcessor {
+    audio_stream_idx: usize,
+    decoder: AudioDecoder,
+    resampler: ResamplerContext,
+    format: AudioFormat,
+    frame_index: usize,
+    next_pts: u64, // in samples, where the resampler's buffered tail goes
+    decoded: AudioFrame,
+    resampled: AudioFrame,
+}
+
+impl AudioProcessor {
+    pub fn stream_index(&self) -> usize {
+        self.audio_stream_idx
+    }
+
+    pub fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// audio is timed in samples
+    pub fn time_base(&self) -> TimeBase {
+        TimeBase::new(1, self.format.sample_rate)
+    }
+
+    fn from_stream(audio_stream: Stream<'_>, format: AudioFormat) -> anyhow::Result<Self> {
+        let index = audio_stream.index();
+
+        let decoder_ctx = CodecContext::from_parameters(audio_stream.parameters())?;
+        let decoder = decoder_ctx.decoder().audio()?;
+
+        let resampler = ResamplerContext::get2(
+            decoder.format(),
+            decoder.ch_layout(),
+            decoder.rate(),
+            Sample::I16(SampleType::Packed),
+            ChannelLayout::default_for_channels(format.channels as u32),
+            format.sample_rate,
+        )?;
+
+        Ok(AudioProcessor {
+            audio_stream_idx: index,
+            decoder,
+            resampler,
+            format,
+            frame_index: 0,
+            next_pts: 0,
+            decoded: AudioFrame::empty(),
+            resampled: AudioFrame::empty(),
+        })
+    }
+
+    fn decode_audioframes(
+        &mut self,
+        stream: &Stream<'_>,
+        tx: &channel::Sender<FFPacket, WithCapacity>,
+    ) -> anyhow::Result<u64> {
+        let mut decoded = 0;
+
+        while self.decoder.receive_frame(&mut self.decoded).is_ok() {
+            let Some(pts) = self.decoded.pts() else {
+                continue;
+            };
+
+            self.resampler.run(&self.decoded, &mut self.resampled)?;
+            if self.resampled.samples() == 0 {
+                continue;
+            }
+
+            let pts = pts.rescale(
+                stream.time_base(),
+                Rational::new(1, self.format.sample_rate as i32),
+            );
+
+            // priming samples (aac, mp3) can start before zero, they're played from the start instead
+            self.send_resampled(stream, pts.max(0) as u64, tx)?;
+            decoded += 1;
+        }
+
+        Ok(decoded)
+    }
+
+    // the samples the resampler still holds on to, at the end of the stream
+    fn flush_resampler(
+        &mut self,
+        stream: &Stream<'_>,
+        tx: &channel::Sender<FFPacket, WithCapacity>,
+    ) -> anyhow::Result<()> {
+        self.resampler.flush(&mut self.resampled)?;
+        if self.resampled.samples() > 0 {
+            self.send_resampled(stream, self.next_pts, tx)?;
+        }
+
+        Ok(())
+    }
+
+    fn send_resampled(
+        &mut self,
+        stream: &Stream<'_>,
+        pts: u64,
+        tx: &channel::Sender<FFPacket, WithCapacity>,
+    ) -> anyhow::Result<()> {
+        let mut packet_slot = tx.send_ref()?;
+        packet_slot.ingest_audio(
+            stream,
+            self.frame_index,
+            self.time_base(),
+            pts,
+            2, // s16
+            &self.resampled,
+        );
+        self.frame_index += 1;
+        self.next_pts = pts + self.resampled.samples() as u64;
+
+        Ok(())
+    }
+
+    fn can_process(&self, idx: usize) -> bool {
+        idx == self.audio_stream_idx
+    }
+}
+
 impl FFDecoder {
     pub fn new(
         path: &str,
         target_x: i64,
         target_y: i64,
+        audio_format: Option<AudioFormat>,
         select_subs: impl FnOnce(StreamIter<'_>) -> Option<Stream<'_>>,
     ) -> anyhow::Result<(Self, channel::Receiver<FFPacket, WithCapacity>)> {
         let input_ctx = ff_input(path)?;
@@ -233,6 +505,23 @@ impl FFDecoder {
 
         let video = VideoProcessor::from_stream(video_stream, target_x, target_y)?;
 
+        // the video might be a picture too, if there's nothing else
+        let attachments = input_ctx
+            .streams()
+            .filter(|s| !video.can_process(s.index()))
+            .filter_map(|s| Attachment::from_stream(s, target_x, target_y))
+            .collect();
+
+        // a file whose audio can't be decoded still gets its video encoded
+        let mut skipped_audio = None;
+        let audio = audio_format.and_then(|format| {
+            let stream = input_ctx.streams().best(StreamType::Audio)?;
+            let index = stream.index();
+            AudioProcessor::from_stream(stream, format)
+                .map_err(|e| skipped_audio = Some((index, e)))
+                .ok()
+        });
+
         let (tx, rx) = channel::with_recycle(
             192,
             WithCapacity::new()
@@ -244,7 +533,10 @@ impl FFDecoder {
             FFDecoder {
                 input_ctx: Some(input_ctx),
                 video,
+                audio,
+                skipped_audio,
                 subs,
+                attachments,
                 packet_tx: tx,
             },
             rx,
@@ -255,11 +547,15 @@ impl FFDecoder {
         self.video.video_stream_idx
     }
 
+    /// the source stream's, frames keep their timestamps
+    pub fn video_time_base(&self) -> TimeBase {
+        self.video.time_base
+    }
+
     pub fn run(mut self) -> anyhow::Result<()> {
         let mut input_ctx = self.input_ctx.take().unwrap();
-        for (stream, mut packet) in input_ctx.packets().filter_map(Result::ok) {
-            packet.rescale_ts(stream.time_base(), MICROSECOND_TIMEBASE);
-
+        // packets keep the source stream's time base, see `FFPacket::time_base`
+        for (stream, packet) in input_ctx.packets().filter_map(Result::ok) {
             if self.video.can_process(stream.index()) {
                 self.video.decoder.send_packet(&packet)?;
                 let _ = self.video.decode_videoframes(&stream, &self.packet_tx)?;
@@ -267,6 +563,15 @@ impl FFDecoder {
                 continue;
             }
 
+            if let Some(audio) = self.audio.as_mut()
+                && audio.can_process(stream.index())
+            {
+                audio.decoder.send_packet(&packet)?;
+                let _ = audio.decode_audioframes(&stream, &self.packet_tx)?;
+
+                continue;
+            }
+
             if let Some(processor) = self.subs.get_mut(&stream.index()) {
                 processor.process_packet(&stream, &packet, &self.packet_tx)?;
             }
@@ -278,9 +583,92 @@ impl FFDecoder {
             &self.packet_tx,
         )?;
 
+        if let Some(audio) = self.audio.as_mut() {
+            let stream = input_ctx.stream(audio.audio_stream_idx).unwrap();
+            audio.decoder.send_eof()?;
+            audio.decode_audioframes(&stream, &self.packet_tx)?;
+            audio.flush_resampler(&stream, &self.packet_tx)?;
+        }
+
         Ok(())
     }
 
+    /// container-level metadata of the source (title, date, ...)
+    pub fn metadata(&self) -> HashMap<String, String> {
+        self.input_ctx
+            .as_ref()
+            .unwrap()
+            .metadata()
+            .iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect()
+    }
+
+    pub fn stream_metadata(&self, index: usize) -> HashMap<String, String> {
+        self.input_ctx
+            .as_ref()
+            .unwrap()
+            .stream(index)
+            .map(|stream| {
+                stream
+                    .metadata()
+                    .iter()
+                    .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// the flags of the source stream that `container::metadata::disposition` has bits for
+    pub fn stream_disposition(&self, index: usize) -> u64 {
+        let Some(stream) = self.input_ctx.as_ref().unwrap().stream(index) else {
+            return 0;
+        };
+
+        [
+            (Disposition::DEFAULT, disposition::DEFAULT),
+            (Disposition::FORCED, disposition::FORCED),
+            (Disposition::HEARING_IMPAIRED, disposition::HEARING_IMPAIRED),
+            (Disposition::COMMENT, disposition::COMMENTARY),
+        ]
+        .into_iter()
+        .filter(|(flag, _)| stream.disposition().contains(*flag))
+        .fold(0, |bits, (_, bit)| bits | bit)
+    }
+
+    /// chapters of the source, sorted by start time, untitled ones are numbered
+    pub fn chapters(&self) -> Vec<Chapter> {
+        let mut chapters: Vec<Chapter> = self
+            .input_ctx
+            .as_ref()
+            .unwrap()
+            .chapters()
+            .enumerate()
+            .map(|(idx, chapter)| {
+                let title = chapter
+                    .metadata()
+                    .get("title")
+                    .map(str::to_owned)
+                    .unwrap_or_else(|| format!("Chapter {}", idx + 1));
+
+                Chapter::new(
+                    chapter
+                        .start()
+                        .rescale(chapter.time_base(), MICROSECOND_TIMEBASE)
+                        .max(0) as u64,
+                    chapter
+                        .end()
+                        .rescale(chapter.time_base(), MICROSECOND_TIMEBASE)
+                        .max(0) as u64,
+                    title,
+                )
+            })
+            .collect();
+
+        chapters.sort_by_key(|chapter| chapter.start);
+        chapters
+    }
+
     pub fn duration(&self) -> Duration {
         Duration::from_micros(
             self.input_ctx
@@ -1,3 +1,4 @@
+use container::metadata::TimeBase;
 pub use ffmpeg_the_third as ffmpeg;
 use ffmpeg_the_third::Rational;
 
@@ -7,3 +8,9 @@ pub const MICROSECOND_TIMEBASE: Rational = Rational(1, 1_000_000);
 pub mod decoder;
 pub mod packet;
 pub mod subtitles;
+
+/// `None` for unset (or negative) time bases
+pub fn to_time_base(rational: Rational) -> Option<TimeBase> {
+    let (num, den) = (rational.numerator(), rational.denominator());
+    (num > 0 && den > 0).then(|| TimeBase::new(num as u32, den as u32))
+}
@@ -1,13 +1,18 @@
-use std::time::Duration;
-
-use container::SubRect;
-use ffmpeg_the_third::{Packet, Stream, frame::Video as VideoFrame, media::Type as StreamType};
+use container::{SubRect, metadata::TimeBase};
+use ffmpeg_the_third::{
+    Packet, Stream,
+    frame::{Audio as AudioFrame, Video as VideoFrame},
+    media::Type as StreamType,
+};
 use thingbuf::{Recycle, recycling};
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum PacketType {
     Video,
+    Audio,
     Subtitle,
+    /// events from a sidecar file, see `encoders::data`
+    Data,
     Unknown,
     Invalid,
 }
@@ -16,8 +21,10 @@ pub struct FFPacket {
     pub stream_idx: usize,
     pub frame_idx: usize, // within the stream
     pub kind: PacketType,
-    pub timestamp: Duration,
-    pub duration: Duration,
+    /// ticks of `time_base`, like `duration`
+    pub timestamp: u64,
+    pub duration: u64,
+    pub time_base: TimeBase,
     pub binary_data: Vec<u8>,
     pub sub_rects: Vec<SubRect>,
 }
@@ -27,6 +34,7 @@ impl FFPacket {
         &mut self,
         stream: &Stream<'_>,
         idx: usize,
+        time_base: TimeBase,
         pts: u64,
         duration: u64,
         packet: &VideoFrame,
@@ -34,15 +42,39 @@ impl FFPacket {
         self.stream_idx = stream.index();
         self.frame_idx = idx;
         self.kind = PacketType::Video;
-        self.timestamp = Duration::from_micros(pts);
-        self.duration = Duration::from_micros(duration);
+        self.timestamp = pts;
+        self.duration = duration;
+        self.time_base = time_base;
         self.binary_data.extend_from_slice(packet.data(0));
     }
 
+    /// expects packed (interleaved) samples, as produced by the audio resampler.
+    /// `time_base` has to be one tick per sample, the duration is the number of samples
+    pub fn ingest_audio(
+        &mut self,
+        stream: &Stream<'_>,
+        idx: usize,
+        time_base: TimeBase,
+        pts: u64,
+        bytes_per_sample: usize,
+        frame: &AudioFrame,
+    ) {
+        self.stream_idx = stream.index();
+        self.frame_idx = idx;
+        self.kind = PacketType::Audio;
+        self.timestamp = pts;
+        self.duration = frame.samples() as u64;
+        self.time_base = time_base;
+
+        let len = frame.samples() * frame.ch_layout().channels() as usize * bytes_per_sample;
+        self.binary_data.extend_from_slice(&frame.data(0)[..len]);
+    }
+
     pub fn ingest_packet(
         &mut self,
         stream: &Stream<'_>,
         idx: usize,
+        time_base: TimeBase,
         with_data: bool,
         packet: &Packet,
     ) {
@@ -50,11 +82,13 @@ impl FFPacket {
         self.frame_idx = idx;
         self.kind = match stream.parameters().medium() {
             StreamType::Video => PacketType::Video,
+            StreamType::Audio => PacketType::Audio,
             StreamType::Subtitle => PacketType::Subtitle,
             _ => PacketType::Unknown,
         };
-        self.duration = Duration::from_micros(packet.duration() as u64);
-        self.timestamp = Duration::from_micros(packet.pts().unwrap() as u64);
+        self.duration = packet.duration() as u64;
+        self.timestamp = packet.pts().unwrap() as u64;
+        self.time_base = time_base;
 
         if with_data && let Some(data) = packet.data() {
             self.binary_data.extend_from_slice(data);
@@ -70,6 +104,7 @@ impl Default for FFPacket {
             kind: PacketType::Invalid,
             timestamp: Default::default(),
             duration: Default::default(),
+            time_base: Default::default(),
             binary_data: Vec::new(),
             sub_rects: Vec::new(),
         }
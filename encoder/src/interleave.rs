@@ -0,0 +1,186 @@
+use std::{cmp::Reverse, collections::BinaryHeap, time::Duration};
+
+/// Holds packets back for up to `window` of stream time, so they come out in timestamp order across streams
+/// even if the decoders hand them over out of order (e.g. video frames behind subtitles because of frame threading).
+/// A packet that comes in after one more than `window` later than it was already popped still goes in,
+/// it comes out behind that one: `max_interleave` says how far, `fits_bound` tells them apart up front.
+pub struct Interleaver<T> {
+    window: Duration,
+    queue: BinaryHeap<Reverse<Queued<T>>>,
+    next_seq: u64,
+    newest: Duration,
+    last_popped: Option<Duration>,
+    max_interleave: Duration,
+}
+
+// ordered by time, packets with the same time in the order they came in
+struct Queued<T> {
+    time: Duration,
+    seq: u64,
+    item: T,
+}
+
+impl<T> PartialEq for Queued<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.time, self.seq) == (other.time, other.seq)
+    }
+}
+
+impl<T> Eq for Queued<T> {}
+
+impl<T> PartialOrd for Queued<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Queued<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.time, self.seq).cmp(&(other.time, other.seq))
+    }
+}
+
+impl<T> Interleaver<T> {
+    /// a zero `window` passes packets through as they come, without a bound
+    pub fn new(window: Duration) -> Self {
+        Interleaver {
+            window,
+            queue: BinaryHeap::new(),
+            next_seq: 0,
+            newest: Duration::ZERO,
+            last_popped: None,
+            max_interleave: Duration::ZERO,
+        }
+    }
+
+    pub fn push(&mut self, time: Duration, item: T) {
+        self.queue.push(Reverse(Queued {
+            time,
+            seq: self.next_seq,
+            item,
+        }));
+        self.next_seq += 1;
+        self.newest = self.newest.max(time);
+    }
+
+    /// whether a packet at `time` would come out within `bound` of everything popped so far
+    pub fn fits_bound(&self, time: Duration) -> bool {
+        match (self.bound(), self.last_popped) {
+            (Some(bound), Some(last_popped)) => time + bound >= last_popped,
+            _ => true,
+        }
+    }
+
+    /// the earliest packet and its time, once everything up to `window` after it has come in
+    pub fn pop_ready(&mut self) -> Option<(Duration, T)> {
+        let Reverse(earliest) = self.queue.peek()?;
+        if self.newest.saturating_sub(earliest.time) < self.window {
+            return None;
+        }
+
+        self.pop()
+    }
+
+    /// the earliest packet, whether or not anything can still come in before it (at the end of the input)
+    pub fn pop(&mut self) -> Option<(Duration, T)> {
+        let Reverse(Queued { time, item, .. }) = self.queue.pop()?;
+
+        // packets that came in late are written behind later ones
+        if let Some(last_popped) = self.last_popped {
+            self.max_interleave = self.max_interleave.max(last_popped.saturating_sub(time));
+        }
+        self.last_popped = Some(self.last_popped.map_or(time, |last| last.max(time)));
+
+        Some((time, item))
+    }
+
+    /// how far behind a packet with a later timestamp any packet popped so far came out
+    pub fn max_interleave(&self) -> Duration {
+        self.max_interleave
+    }
+
+    /// what `max_interleave` stays within as long as every packet `fits_bound`, `None` for a zero window
+    pub fn bound(&self) -> Option<Duration> {
+        (!self.window.is_zero()).then_some(self.window)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::Interleaver;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn test_orders_across_streams() {
+        let mut interleaver = Interleaver::new(ms(100));
+        let mut popped = Vec::new();
+
+        // video frames held up behind subtitles and audio
+        for (time, item) in [(0, "a0"), (20, "s20"), (40, "a40"), (0, "v0"), (40, "v40")] {
+            interleaver.push(ms(time), item);
+        }
+        assert_eq!(interleaver.pop_ready(), None);
+
+        for (time, item) in [(80, "v80"), (120, "v120"), (140, "a140")] {
+            interleaver.push(ms(time), item);
+            while let Some((_, item)) = interleaver.pop_ready() {
+                popped.push(item);
+            }
+        }
+
+        // same timestamps come out in the order they went in
+        assert_eq!(popped, ["a0", "v0", "s20", "a40", "v40"]);
+        assert_eq!(interleaver.max_interleave(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_window_bound() {
+        let mut interleaver = Interleaver::new(ms(100));
+        for time in [0, 200, 400] {
+            interleaver.push(ms(time), time);
+        }
+        while interleaver.pop_ready().is_some() {}
+
+        // 200 is out, 150 is late but inside the window
+        assert!(interleaver.fits_bound(ms(150)));
+        interleaver.push(ms(150), 150);
+        assert_eq!(interleaver.pop(), Some((ms(150), 150)));
+        assert_eq!(interleaver.max_interleave(), ms(50));
+
+        // 99 is further behind than the window, it's still written
+        assert!(!interleaver.fits_bound(ms(99)));
+        interleaver.push(ms(99), 99);
+        assert_eq!(interleaver.pop(), Some((ms(99), 99)));
+        assert_eq!(interleaver.max_interleave(), ms(101));
+
+        // without a window nothing is held back or turned away
+        let mut passthrough = Interleaver::new(Duration::ZERO);
+        passthrough.push(ms(400), 400);
+        assert_eq!(passthrough.pop_ready(), Some((ms(400), 400)));
+        assert!(passthrough.fits_bound(ms(0)));
+        passthrough.push(ms(0), 0);
+        assert_eq!(passthrough.pop_ready(), Some((ms(0), 0)));
+        assert_eq!(passthrough.max_interleave(), ms(400));
+        assert_eq!(passthrough.bound(), None);
+    }
+
+    #[test]
+    fn test_flushes_on_finish() {
+        let mut interleaver = Interleaver::new(ms(1000));
+        for time in [40, 0, 80, 20] {
+            interleaver.push(ms(time), time);
+        }
+        assert_eq!(interleaver.pop_ready(), None);
+
+        let mut flushed = Vec::new();
+        while let Some((_, time)) = interleaver.pop() {
+            flushed.push(time);
+        }
+        assert_eq!(flushed, [0, 20, 40, 80]);
+    }
+}
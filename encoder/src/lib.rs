@@ -1,2 +1,3 @@
 pub mod encoders;
 pub mod ff;
+pub mod interleave;
@@ -1,9 +1,11 @@
 use std::{
+    collections::HashMap,
     fs::File,
-    io::{BufReader, BufWriter, Seek, Write},
+    io::{BufWriter, Seek, SeekFrom, Write},
     ops::Deref,
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
+    time::Duration,
 };
 
 use byteorder::{LittleEndian, WriteBytesExt};
@@ -13,20 +15,32 @@ use clap::{
 };
 use colorful::{bluenoise::Bluenoise, pattern_dithering::MatrixSize};
 use container::{
-    EncodableData, FormatDuration, Packet,
-    metadata::{ColorMode, CompressionMode, SubtitleParameters, VideoParameters},
+    EncodableData, FormatDuration, Packet, PacketDataType, SYNC_MARKER, encryption,
+    header::{HeaderCodec, HeaderVersion},
+    index::{IndexEntry, PacketIndex},
+    metadata::{
+        ANSI_STILL_MIME, AttachmentParameters, AudioCodec, AudioParameters, ColorMode,
+        CompressionMode, FormatData, MetadataTag, StreamStats, SubtitleParameters, TimeBase,
+        VideoCodec, VideoParameters, features, tags as metadata_tags,
+    },
+    preamble::{Layout, Preamble, TRAILER_OFFSET_POSITION, VERSION_MAJOR, VERSION_MINOR},
+    side_data,
 };
 use encoder::{
     encoders::{
         Pipeline, SeekTableEncoder,
+        audio::PcmAudioEncoder,
+        data::{DataEncoder, SidecarSource},
+        encrypt::Encryptor,
         subtitles::AnsiSubtitleEncoder,
-        video::{AnsiVideoEncoder, DitherMethod},
+        video::{AnsiVideoEncoder, DitherMethod, FrameHistory, render_still},
     },
     ff::{self},
 };
 use encoder::{
     encoders::{lz4::Lz4Compressor, zstd::ZstdCompressor},
-    ff::decoder::FFDecoder,
+    ff::decoder::{AudioFormat, FFDecoder},
+    interleave::Interleaver,
 };
 use litemap::LiteMap;
 use rasn::types::OctetString;
@@ -36,8 +50,31 @@ use rasn::types::OctetString;
 pub struct EncoderArgs {
     #[arg(short, long, value_name = "FILE")]
     input: String,
+    /// Output file, or "-" to stream to stdout
     #[arg(short, long, value_name = "FILE")]
     output: PathBuf,
+    /// Write a fragmented stream that can be played while it's still being written (always on for stdout)
+    #[arg(long)]
+    fragmented: bool,
+    /// Target length of a fragment, in seconds
+    #[arg(long, default_value_t = 2.0)]
+    fragment_duration: f64,
+    /// How long packets are held back to write them in timestamp order across streams, in milliseconds
+    /// (0 = no reordering). Fragmented output fails on a packet coming in later than that
+    #[arg(long, default_value_t = 1000)]
+    interleave_window: u64,
+    /// Minimum distance between two seek points of a stream, in milliseconds
+    #[arg(long, default_value_t = 100)]
+    seek_resolution: u64,
+    /// Don't store a CRC32C with every packet
+    #[arg(long)]
+    no_checksums: bool,
+    /// Don't store an index of every packet in the trailer (only written for seekable files)
+    #[arg(long)]
+    no_packet_index: bool,
+    /// Store pre-rendered escape sequences (ansi) or a binary cell grid rendered at playback time (cells)
+    #[arg(long, default_value_t = VideoCodec::Ansi, value_parser = PossibleValuesParser::new(["ansi", "cells"]).try_map(|v| VideoCodec::from_str(&v)))]
+    video_codec: VideoCodec,
     #[arg(long, default_value_t = ColorMode::Full, value_parser = PossibleValuesParser::new(["full", "8bit"]).try_map(|v| ColorMode::from_str(&v)))]
     color_mode: ColorMode,
     /// How to dither this image!
@@ -61,64 +98,444 @@ pub struct EncoderArgs {
     noise_map: Option<PathBuf>,
     #[arg(long, default_value_t = 64.00f64)]
     noise_range: f64,
+    /// Maximum distance between full frames; frames in between only carry the cells that changed (0 or 1 = every frame is a full frame)
+    #[arg(long, default_value_t = 120)]
+    keyframe_interval: u32,
+    /// Store frames identical to the one before them in full, instead of as repeat packets
+    #[arg(long)]
+    no_repeat_frames: bool,
+    /// Sidecar file with timed events (one JSON object per line: start, duration, and text, json or bytes),
+    /// stored as a data stream. Can be given more than once
+    #[arg(long, value_name = "FILE")]
+    data: Vec<PathBuf>,
+    /// Don't include an audio track
+    #[arg(long)]
+    no_audio: bool,
+    #[arg(long, default_value_t = 48_000)]
+    audio_sample_rate: u32,
+    #[arg(long, default_value_t = 2)]
+    audio_channels: u8,
+    /// Encrypt every packet payload with the key in this file (64 hex digits), the header stays readable
+    #[arg(long, value_name = "FILE")]
+    encryption_key: Option<PathBuf>,
+    /// Stored with every encrypted packet, so players holding several keys can pick the right one
+    #[arg(long, requires = "encryption_key")]
+    encryption_key_id: Option<String>,
+}
+
+/// Deletes the output file when dropped before `keep`, so a failed encode doesn't leave behind
+/// a file that looks fine up until its trailer.
+struct PartialOutput(Option<PathBuf>);
+
+impl PartialOutput {
+    fn keep(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for PartialOutput {
+    fn drop(&mut self) {
+        if let Some(path) = self.0.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// the command line as stored in the file, without the encryption key arguments and their values
+fn encode_settings(args: impl Iterator<Item = String>) -> String {
+    const SECRET: [&str; 2] = ["--encryption-key", "--encryption-key-id"];
+
+    let mut kept = Vec::new();
+    let mut skip_value = false;
+    for arg in args {
+        if std::mem::take(&mut skip_value) {
+            continue;
+        }
+
+        let name = arg.split_once('=').map_or(arg.as_str(), |(name, _)| name);
+        if SECRET.contains(&name) {
+            skip_value = !arg.contains('=');
+            continue;
+        }
+
+        kept.push(arg);
+    }
+
+    kept.join(" ")
+}
+
+/// where the encoded packets end up
+pub enum Output {
+    /// seekable file: packets first, header + seek tables in a trailer
+    File(BufWriter<File>),
+    /// anything else (e.g. stdout): self-contained fragments, each one buffered until it's complete
+    Fragmented {
+        out: BufWriter<Box<dyn Write + Send>>,
+        fragment: Vec<u8>,
+        started_at: Option<Duration>,
+    },
+}
+
+impl Output {
+    pub fn file(file: File) -> std::io::Result<Output> {
+        let mut out = BufWriter::new(file);
+
+        // the trailer offset is patched in by `ANSIEncoder::finish`
+        Preamble::new(Layout::Trailer { trailer_offset: 0 }).encode_into(&mut out)?;
+
+        Ok(Output::File(out))
+    }
+
+    pub fn fragmented(out: Box<dyn Write + Send>) -> std::io::Result<Output> {
+        let mut out = BufWriter::new(out);
+        Preamble::new(Layout::Fragmented).encode_into(&mut out)?;
+
+        Ok(Output::Fragmented {
+            out,
+            fragment: Vec::new(),
+            started_at: None,
+        })
+    }
 }
 
 #[allow(dead_code)]
 pub struct ANSIEncoder {
-    out: BufWriter<File>,
+    out: Output,
+    format_data: Option<FormatData>,
+    header: Vec<u8>,
     scratch: Vec<u8>,
     stream_packet_idx: LiteMap<u8, u64>,
     encoders: LiteMap<u8, Pipeline>,
     width: i64,
-    height: i64,                  // seek_table:
-    seek_table: SeekTableEncoder, // every n milliseconds, record a seektable entry
-    bytes_written: u64,
+    height: i64,
+    video_stream: u8,
+    seek_tables: LiteMap<u8, SeekTableEncoder>, // one per stream
+    seek_resolution: u64,
+    headers: HeaderCodec,
+    fragment_duration: Duration,
+    checksums: bool,
+    bytes_written: u64, // since the first packet (or the start of the current fragment)
+    index: Option<PacketIndex>,
+    interleaver: Interleaver<(Packet, Vec<u8>)>, // encoded packets and their data
+    stats: LiteMap<u8, StreamStats>,
+    encryptor: Option<Encryptor>, // runs in `write_packet`, once the packet index is known
 }
 
 impl ANSIEncoder {
-    pub fn new(out: BufWriter<File>, args: &EncoderArgs) -> Self {
+    pub fn new(out: Output, args: &EncoderArgs) -> Self {
+        let index =
+            (matches!(out, Output::File(_)) && !args.no_packet_index).then(PacketIndex::new);
+
         Self {
             out,
+            format_data: None,
+            header: Vec::new(),
             scratch: Vec::with_capacity(args.width as usize * args.height as usize * 20),
             stream_packet_idx: LiteMap::new(),
             encoders: LiteMap::new(),
             width: args.width,
             height: args.height,
-            seek_table: SeekTableEncoder::new(0),
+            video_stream: 0,
+            seek_tables: LiteMap::new(),
+            seek_resolution: args.seek_resolution,
+            headers: HeaderCodec::new(HeaderVersion::for_format_version(VERSION_MAJOR)),
+            fragment_duration: Duration::from_secs_f64(args.fragment_duration),
+            checksums: !args.no_checksums,
             bytes_written: 0,
+            index,
+            interleaver: Interleaver::new(Duration::from_millis(args.interleave_window)),
+            stats: LiteMap::new(),
+            encryptor: None,
         }
     }
 
-    fn add_encoder(&mut self, stream: u8, pipeline: Pipeline) {
+    fn add_encoder(&mut self, stream: u8, time_base: TimeBase, pipeline: Pipeline) {
         self.encoders.insert(stream, pipeline);
+        self.seek_tables.insert(
+            stream,
+            SeekTableEncoder::new(stream, time_base, self.seek_resolution),
+        );
+    }
+
+    fn set_format_data(&mut self, format_data: &FormatData) -> std::io::Result<()> {
+        self.format_data = Some(format_data.clone());
+        self.encode_header()
+    }
+
+    // the interleave distance and stream stats grow as packets get written, every copy of the header has the latest ones
+    fn encode_header(&mut self) -> std::io::Result<()> {
+        let Some(format_data) = &mut self.format_data else {
+            return Ok(());
+        };
+
+        // a fragment's header has to hold for the packets after it as well, a trailer only for what's been written
+        let max_interleave = match self.out {
+            Output::File(_) => Some(self.interleaver.max_interleave()),
+            Output::Fragmented { .. } => self.interleaver.bound(),
+        };
+        format_data.max_interleave = max_interleave.map(|max| max.as_nanos().div_ceil(1000) as u64);
+        for track in &mut format_data.tracks {
+            track.stats = Some(self.stats.get(&track.index).copied().unwrap_or_default());
+        }
+        self.header = rasn::der::encode(format_data).map_err(std::io::Error::other)?;
+        Ok(())
     }
 
+    /// writes the whole file of an attachment stream, before any other packet
+    fn write_attachment(&mut self, stream: u8, data: Vec<u8>) -> std::io::Result<()> {
+        let mut packet = Packet::builder()
+            .stream(stream)
+            .timestamp(0)
+            .duration(0)
+            .build();
+        packet.data_type = PacketDataType::Attachment;
+        packet.data_len = data.len() as u64;
+
+        self.write_packet(packet, data, Duration::ZERO)
+    }
+
+    /// encodes a packet, it gets written once the packets around it in time have come in
     fn process_packet(&mut self, input: &encoder::ff::packet::FFPacket) -> std::io::Result<()> {
         let mut packet = Packet::builder()
             .timestamp(input.timestamp)
             .duration(input.duration)
             .stream(input.stream_idx as u8)
             .build();
+        let time = input.time_base.to_duration(input.timestamp);
 
         let Some(encoder) = self.encoders.get_mut(&packet.stream) else {
             return Ok(());
         };
 
-        self.scratch.clear();
-        encoder.run(input, &mut packet, &mut self.scratch)?;
+        let mut data = std::mem::take(&mut self.scratch);
+        data.clear();
+        encoder.run(input, &mut packet, &mut data)?;
+        // fragments have already gone out claiming the window as the bound, a file's trailer records how late it was
+        if matches!(self.out, Output::Fragmented { .. }) && !self.interleaver.fits_bound(time) {
+            return Err(std::io::Error::other(format!(
+                "packet of stream {} at {} came in more than the interleave window late, try a larger --interleave-window",
+                packet.stream,
+                FormatDuration(time)
+            )));
+        }
+
+        self.interleaver.push(time, (packet, data));
+
+        while let Some((time, (packet, data))) = self.interleaver.pop_ready() {
+            self.write_packet(packet, data, time)?;
+        }
+
+        Ok(())
+    }
 
+    fn write_packet(
+        &mut self,
+        mut packet: Packet,
+        mut data: Vec<u8>,
+        time: Duration,
+    ) -> std::io::Result<()> {
         let index = self.stream_packet_idx.entry(packet.stream).or_insert(1);
         packet.packet_idx = *index;
         *index += 1;
 
-        self.seek_table.ingest(&packet, self.bytes_written);
+        // repeats have no payload to seal
+        if let Some(encryptor) = &mut self.encryptor
+            && packet.data_type != PacketDataType::Repeat
+        {
+            encryptor.seal(&mut packet, &mut data)?;
+        }
+
+        if self.checksums {
+            packet.set_checksum(&data);
+        }
+
+        let decompressed_len = packet
+            .side_data
+            .get_u64(&side_data::DECOMPRESSED_LEN)
+            .unwrap_or(packet.data_len);
+        self.stats
+            .entry(packet.stream)
+            .or_default()
+            .add(packet.data_len, decompressed_len);
+
+        let is_video_keyframe = packet.stream == self.video_stream && packet.is_keyframe();
+
+        // fragments are cut right before a video keyframe, so each one can be played on its own
+        if let Output::Fragmented {
+            started_at: Some(started_at),
+            ..
+        } = &self.out
+            && is_video_keyframe
+            && time.saturating_sub(*started_at) >= self.fragment_duration
+        {
+            self.flush_fragment()?;
+        }
+
+        let is_seek_point = self
+            .seek_tables
+            .get_mut(&packet.stream)
+            .is_some_and(|seek_table| seek_table.ingest(&packet, self.bytes_written));
+
+        // readers start decoding at seek points and markers, they need an absolute timestamp there
+        if is_seek_point || is_video_keyframe {
+            self.headers.reset();
+        }
+
+        let mut out: &mut dyn Write = match &mut self.out {
+            Output::File(out) => out,
+            Output::Fragmented {
+                fragment,
+                started_at,
+                ..
+            } => {
+                started_at.get_or_insert(time);
+                fragment
+            }
+        };
+
+        // seek entries point at the marker, a reader skips over it
+        if is_video_keyframe {
+            out.write_all(&SYNC_MARKER)?;
+            self.bytes_written += SYNC_MARKER.len() as u64;
+        }
+
+        let offset = self.bytes_written;
+        self.bytes_written += self.headers.encode_into(&packet, &mut out)?;
+        out.write_all(&data)?;
+        self.bytes_written += data.len() as u64;
+
+        if let Some(index) = &mut self.index {
+            index.push(IndexEntry {
+                stream: packet.stream,
+                packet_idx: packet.packet_idx,
+                timestamp: packet.timestamp as i64,
+                offset,
+                size: self.bytes_written - offset,
+                keyframe: !matches!(
+                    packet.data_type,
+                    PacketDataType::Video | PacketDataType::Repeat
+                ) || packet.is_keyframe(),
+            });
+        }
+
+        // the buffer of the last packet written gets reused for the next one
+        self.scratch = data;
+
+        Ok(())
+    }
+
+    /// writes out the buffered fragment: header, seek table for just this fragment and its packets
+    fn flush_fragment(&mut self) -> std::io::Result<()> {
+        self.encode_header()?;
+
+        let Output::Fragmented {
+            out,
+            fragment,
+            started_at,
+        } = &mut self.out
+        else {
+            return Ok(());
+        };
+
+        if fragment.is_empty() {
+            return Ok(());
+        }
+
+        out.write_u64::<LittleEndian>(self.header.len() as u64)?;
+        out.write_all(&self.header)?;
+
+        write_seek_tables(out, &mut self.seek_tables, self.video_stream)?;
+
+        out.write_u64::<LittleEndian>(fragment.len() as u64)?;
+        out.write_all(fragment)?;
+        out.flush()?;
 
-        self.bytes_written += packet.encode_into(&mut self.out)?;
-        self.out.write_all(&self.scratch)?;
-        self.bytes_written += self.scratch.len() as u64;
+        fragment.clear();
+        *started_at = None;
+        self.bytes_written = 0;
+        self.headers.reset();
 
         Ok(())
     }
+
+    /// writes the trailer (header, seek tables, packet index) after the last packet and points the preamble at it
+    fn finish(mut self) -> std::io::Result<()> {
+        while let Some((time, (packet, data))) = self.interleaver.pop() {
+            self.write_packet(packet, data, time)?;
+        }
+
+        self.encode_header()?;
+
+        let Output::File(out) = &mut self.out else {
+            return self.flush_fragment();
+        };
+
+        let trailer_offset = out.stream_position()?;
+
+        out.write_u64::<LittleEndian>(self.header.len() as u64)?;
+        out.write_all(&self.header)?;
+
+        write_seek_tables(out, &mut self.seek_tables, self.video_stream)?;
+        if let Some(index) = &self.index {
+            index.encode_into(out)?;
+        }
+
+        out.seek(SeekFrom::Start(TRAILER_OFFSET_POSITION))?;
+        out.write_u64::<LittleEndian>(trailer_offset)?;
+        out.flush()
+    }
+}
+
+/// writes a seek table section, video first (older readers only look at the first table)
+fn write_seek_tables(
+    out: &mut impl Write,
+    seek_tables: &mut LiteMap<u8, SeekTableEncoder>,
+    video_stream: u8,
+) -> std::io::Result<()> {
+    let mut tables: Vec<&mut SeekTableEncoder> = seek_tables
+        .iter_mut()
+        .map(|(_, table)| table)
+        .filter(|table| !table.is_empty())
+        .collect();
+    tables.sort_by_key(|table| table.stream_index() != video_stream);
+
+    out.write_u8(tables.len() as u8)?;
+    for table in tables {
+        out.write_all(&table.finish())?;
+    }
+
+    Ok(())
+}
+
+fn attachment_stream(
+    index: u8,
+    parameters: AttachmentParameters,
+    metadata: &HashMap<String, String>,
+) -> container::metadata::Stream {
+    container::metadata::Stream {
+        name: parameters.filename.clone(),
+        index,
+        duration: 0,
+        extradata: OctetString::default(),
+        compression_dict: None,
+        parameters: container::metadata::CodecParameters::Attachment(parameters),
+        compression_mode: CompressionMode::None,
+        tags: Some(to_tags(metadata)),
+        time_base: Some(TimeBase::MICROS),
+        stats: None,
+        disposition: None,
+    }
+}
+
+/// ffmpeg metadata as tags, sorted so encodes of the same source come out the same
+fn to_tags(metadata: &HashMap<String, String>) -> Vec<MetadataTag> {
+    let mut tags: Vec<MetadataTag> = metadata
+        .iter()
+        .map(|(key, value)| MetadataTag::new(key.to_lowercase(), value.as_str()))
+        .collect();
+
+    tags.sort_by(|a, b| a.key.cmp(&b.key));
+    tags
 }
 
 fn main() -> anyhow::Result<()> {
@@ -126,23 +543,44 @@ fn main() -> anyhow::Result<()> {
 
     let cli = EncoderArgs::parse();
 
-    let (ff_decoder, rx) = FFDecoder::new(&cli.input, cli.width, cli.height, |subs| {
-        subs.best(ffmpeg_the_third::media::Type::Subtitle)
-    })?;
+    let audio_format = (!cli.no_audio).then_some(AudioFormat {
+        sample_rate: cli.audio_sample_rate,
+        channels: cli.audio_channels,
+    });
 
-    let mut ansi_encoder = ANSIEncoder::new(
-        BufWriter::new(tempfile::tempfile_in(std::env::current_dir()?)?),
-        &cli,
-    );
+    let (ff_decoder, rx) = FFDecoder::new(
+        &cli.input,
+        cli.width,
+        cli.height,
+        audio_format,
+        |subs| subs.best(ffmpeg_the_third::media::Type::Subtitle),
+    )?;
+
+    let partial_output = PartialOutput((cli.output != Path::new("-")).then(|| cli.output.clone()));
+    let output = if cli.output == Path::new("-") {
+        Output::fragmented(Box::new(std::io::stdout()))?
+    } else if cli.fragmented {
+        Output::fragmented(Box::new(File::create(&cli.output)?))?
+    } else {
+        Output::file(File::create(&cli.output)?)?
+    };
+
+    let mut ansi_encoder = ANSIEncoder::new(output, &cli);
+
+    if let Some(key_path) = &cli.encryption_key {
+        let key = encryption::parse_key(&std::fs::read_to_string(key_path)?).ok_or_else(|| {
+            anyhow::anyhow!("{}: expected a key of 64 hex digits", key_path.display())
+        })?;
+        let key_id = cli.encryption_key_id.clone().map(String::into_bytes);
+        ansi_encoder.encryptor = Some(Encryptor::new(&key, key_id));
+    }
 
     // let dict = std::fs::read("full-color-anime.zstdict")?;
 
     let mut streams = vec![];
 
     let video_stream_idx = ff_decoder.video_stream_idx();
-    ansi_encoder
-        .seek_table
-        .set_stream_index(video_stream_idx as u8);
+    ansi_encoder.video_stream = video_stream_idx as u8;
 
     streams.push(container::metadata::Stream {
         name: "video".to_string(),
@@ -154,8 +592,15 @@ fn main() -> anyhow::Result<()> {
             width: cli.width as u16,
             height: cli.height as u16,
             color: cli.color_mode,
+            keyframe_interval: (cli.video_codec == VideoCodec::Ansi)
+                .then_some(cli.keyframe_interval),
+            codec: Some(cli.video_codec),
         }),
         compression_mode: CompressionMode::Zstd,
+        tags: Some(to_tags(&ff_decoder.stream_metadata(video_stream_idx))),
+        time_base: Some(ff_decoder.video_time_base()),
+        stats: None,
+        disposition: Some(ff_decoder.stream_disposition(video_stream_idx)),
     });
 
     let blue_noise = if let Some(noise_path) = cli.noise_map.as_ref() {
@@ -167,7 +612,9 @@ fn main() -> anyhow::Result<()> {
 
     ansi_encoder.add_encoder(
         ff_decoder.video_stream_idx() as u8,
+        ff_decoder.video_time_base(),
         Pipeline::new(AnsiVideoEncoder {
+            codec: cli.video_codec,
             color_mode: cli.color_mode,
             dither_mode: cli.dither_method,
             matrix_size: cli.matrix_size,
@@ -175,10 +622,44 @@ fn main() -> anyhow::Result<()> {
             width: cli.width,
             height: cli.height,
             blue_noise,
+            frames: FrameHistory::new(cli.keyframe_interval as u64)
+                .with_repeats(!cli.no_repeat_frames),
         })
         .with_step(ZstdCompressor::new(8)?), // .with_step(ZstdCompressor::with_dict(3, dict)?),
     );
 
+    if let Some((index, e)) = &ff_decoder.skipped_audio {
+        eprintln!("skipping audio stream {index}: {e}");
+    }
+
+    if let Some(audio) = ff_decoder.audio.as_ref() {
+        let format = audio.format();
+
+        streams.push(container::metadata::Stream {
+            name: "audio".to_string(),
+            index: audio.stream_index() as u8,
+            duration: ff_decoder.duration().as_micros() as u64,
+            extradata: OctetString::default(),
+            compression_dict: None,
+            parameters: container::metadata::CodecParameters::Audio(AudioParameters {
+                sample_rate: format.sample_rate,
+                channels: format.channels,
+                codec: AudioCodec::Pcm,
+            }),
+            compression_mode: CompressionMode::None,
+            tags: Some(to_tags(&ff_decoder.stream_metadata(audio.stream_index()))),
+            time_base: Some(audio.time_base()),
+            stats: None,
+            disposition: Some(ff_decoder.stream_disposition(audio.stream_index())),
+        });
+
+        ansi_encoder.add_encoder(
+            audio.stream_index() as u8,
+            audio.time_base(),
+            Pipeline::new(PcmAudioEncoder),
+        );
+    }
+
     for subtitle_track in ff_decoder.subs.values() {
         streams.push(container::metadata::Stream {
             name: subtitle_track
@@ -200,69 +681,179 @@ fn main() -> anyhow::Result<()> {
                 play_height: cli.height as u16,
             }),
             compression_mode: CompressionMode::Lz4,
+            tags: Some(to_tags(subtitle_track.metadata())),
+            time_base: Some(subtitle_track.time_base()),
+            stats: None,
+            disposition: Some(ff_decoder.stream_disposition(subtitle_track.stream_index())),
         });
 
         ansi_encoder.add_encoder(
             subtitle_track.stream_index() as u8,
+            subtitle_track.time_base(),
             Pipeline::new(AnsiSubtitleEncoder).with_step(Lz4Compressor::default()),
         );
     }
 
+    // renderings of cover art and data streams get stream indexes past every one of the source
+    let mut free_index = streams
+        .iter()
+        .map(|stream| stream.index)
+        .chain(
+            ff_decoder
+                .attachments
+                .iter()
+                .map(|attachment| attachment.stream_index as u8),
+        )
+        .max()
+        .map_or(0, |index| index + 1);
+
+    let mut attachments = vec![];
+    for attachment in &ff_decoder.attachments {
+        let index = attachment.stream_index as u8;
+        let parameters = AttachmentParameters {
+            mime_type: attachment.mime_type.clone(),
+            filename: attachment.filename.clone(),
+            cover: attachment.picture.is_some().then_some(true),
+        };
+
+        streams.push(attachment_stream(index, parameters, &attachment.metadata));
+        attachments.push((index, attachment.data.clone()));
+
+        if let Some(picture) = attachment.picture.as_ref() {
+            let parameters = AttachmentParameters {
+                mime_type: ANSI_STILL_MIME.to_string(),
+                filename: "cover.ans".to_string(),
+                cover: Some(true),
+            };
+
+            streams.push(attachment_stream(free_index, parameters, &HashMap::new()));
+            attachments.push((free_index, render_still(picture, cli.color_mode)?));
+            free_index += 1;
+        }
+    }
+
+    let mut sidecars = vec![];
+    for path in &cli.data {
+        let source = SidecarSource::open(path, free_index as usize)?;
+
+        streams.push(container::metadata::Stream {
+            name: path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "data".to_string()),
+            index: free_index,
+            duration: ff_decoder.duration().as_micros() as u64,
+            extradata: OctetString::default(),
+            compression_dict: None,
+            parameters: container::metadata::CodecParameters::Data(source.parameters().clone()),
+            compression_mode: CompressionMode::None,
+            tags: None,
+            time_base: Some(source.time_base()),
+            stats: None,
+            disposition: None,
+        });
+
+        ansi_encoder.add_encoder(free_index, source.time_base(), Pipeline::new(DataEncoder));
+        sidecars.push(source);
+        free_index += 1;
+    }
+
+    let video_features = match cli.video_codec {
+        VideoCodec::Ansi if cli.keyframe_interval > 1 => features::DELTA_FRAMES,
+        VideoCodec::Ansi => 0,
+        VideoCodec::CellGrid => features::CELL_GRID,
+    };
+    let encryption_features = if cli.encryption_key.is_some() {
+        features::ENCRYPTED
+    } else {
+        0
+    };
+    // repeats only get written where deltas could be
+    let repeat_features = if !cli.no_repeat_frames && cli.keyframe_interval > 1 {
+        features::REPEAT_FRAMES
+    } else {
+        0
+    };
+
+    let chapters = ff_decoder.chapters();
+
+    let mut tags = to_tags(&ff_decoder.metadata());
+    if let Some(source) = Path::new(&cli.input).file_name() {
+        tags.push(MetadataTag::new(
+            metadata_tags::SOURCE_FILE,
+            source.to_string_lossy(),
+        ));
+    }
+    tags.push(MetadataTag::new(
+        metadata_tags::ENCODE_SETTINGS,
+        encode_settings(std::env::args().skip(1)),
+    ));
+
     let format_data = container::metadata::FormatData {
-        format_name: "ansi.moe v3.0 (codename yachi-yo!)".to_string(),
+        format_name: format!("ansi.moe v{VERSION_MAJOR}.{VERSION_MINOR} (codename yachi-yo!)"),
         encoder: "ansi.moe ref encoder".to_string(),
         tracks: streams,
+        features: Some(
+            video_features | repeat_features | encryption_features | features::SYNC_MARKERS,
+        ),
+        chapters: (!chapters.is_empty()).then_some(chapters),
+        tags: Some(tags),
+        max_interleave: None, // filled in as packets get written
     };
 
+    ansi_encoder.set_format_data(&format_data)?;
+    for (stream, data) in attachments {
+        ansi_encoder.write_attachment(stream, data)?;
+    }
+
     let total_duration = FormatDuration(ff_decoder.duration());
 
     let receiver = std::thread::spawn(move || -> anyhow::Result<()> {
         while let Some(slot) = rx.recv_ref() {
-            let time = slot.timestamp;
+            let time = slot.time_base.to_duration(slot.timestamp);
 
             let pct = time.as_secs_f64() / total_duration.0.as_secs_f64();
-            print!("\x1b[2K\r");
-            print!(
+            // progress goes to stderr, stdout might be carrying the stream
+            eprint!("\x1b[2K\r");
+            eprint!(
                 "{}/{} ({:.2}%)",
                 FormatDuration(time),
                 total_duration,
                 pct * 100.0
             );
-            std::io::stdout().flush().unwrap();
+            std::io::stderr().flush().unwrap();
 
             // slot.timestamp
 
+            // sidecar events go in along with the decoded packets around them in time
+            for source in sidecars.iter_mut() {
+                while let Some(event) = source.next_until(time) {
+                    ansi_encoder.process_packet(&event)?;
+                }
+            }
+
             ansi_encoder.process_packet(slot.deref())?;
         }
 
-        let mut packets_file = ansi_encoder.out.into_inner().unwrap();
-        packets_file.seek(std::io::SeekFrom::Start(0))?;
-
-        // finalization
-        let mut final_out = BufWriter::new(File::create(cli.output)?);
-        let header = rasn::der::encode(&format_data).unwrap();
-        final_out.write_u64::<LittleEndian>(header.len() as u64)?;
-        final_out.write_all(&header)?;
-        // final_out.write_all(&rasn::der::encode(&format_data).unwrap())?;
-
-        let seek_video_table = ansi_encoder.seek_table.finish();
-        final_out.write_u8(1)?; // one seek table
-        final_out.write_all(&seek_video_table)?;
-
-        std::io::copy(&mut BufReader::new(packets_file), &mut final_out)?;
-        //         -- (marker: len_bytes, u64) Header: DER-encoded FormatData
-        // -- (marker: len_bytes, u64) Seek Tables
-        //     -- (stream_index: u16)
-        //     -- (seek_table_length: u64 / bytes)
-        // -- (interleaved packet data)
+        for source in sidecars.iter_mut() {
+            while let Some(event) = source.next_until(Duration::MAX) {
+                ansi_encoder.process_packet(&event)?;
+            }
+        }
 
-        // out.write_all()
+        ansi_encoder.finish()?;
 
         Ok(())
     });
 
-    ff_decoder.run();
-    receiver.join();
+    let decoded = ff_decoder.run();
+    let encoded = receiver
+        .join()
+        .unwrap_or_else(|_| Err(anyhow::anyhow!("encoder thread panicked")));
+
+    // the encoder's error first: the decoder only fails on a closed channel once it's gone
+    encoded.and(decoded)?;
+    partial_output.keep();
 
     Ok(())
 }
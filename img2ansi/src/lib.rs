@@ -7,7 +7,7 @@ use std::{
 
 use byteorder::WriteBytesExt;
 use colorful::palette::*;
-use container::{EncodableData, PacketDataType, TypedData};
+use container::{EncodableData, PacketDataType, TypedData, cells::CellFrame, metadata::ColorMode};
 use image::{GenericImageView, Luma, Rgb};
 
 pub trait AnsiPixel: PartialEq {
@@ -103,6 +103,13 @@ impl<T: ToAnsi> TypedData for AnsiFrame<T> {
 pub trait ToAnsi {
     fn to_ansi(&self, frame: &mut impl Write) -> std::io::Result<()>;
 
+    /// only draws the cells that changed since `previous`, positioning the cursor explicitly.
+    /// falls back to a full frame when there is nothing to diff against.
+    fn to_ansi_delta(&self, previous: &Self, frame: &mut impl Write) -> std::io::Result<()> {
+        let _ = previous;
+        self.to_ansi(frame)
+    }
+
     fn est_size(&self) -> Option<usize> {
         None
     }
@@ -141,7 +148,106 @@ where
         Ok(())
     }
 
+    fn to_ansi_delta(&self, previous: &Self, frame: &mut impl Write) -> std::io::Result<()> {
+        if previous.dimensions() != self.dimensions() {
+            return self.to_ansi(frame);
+        }
+
+        let mut last_upper: Option<T::Pixel> = None;
+        let mut last_lower: Option<T::Pixel> = None;
+
+        // (column, row) the terminal cursor is at, if known
+        let mut cursor: Option<(u32, u32)> = None;
+        let mut buffer = itoa::Buffer::new();
+
+        for y in (0..self.height() - 1).step_by(2) {
+            let row = y / 2;
+
+            for x in 0..self.width() {
+                let upper = self.get_pixel(x, y);
+                let lower = self.get_pixel(x, y + 1);
+
+                if previous.get_pixel(x, y) == upper && previous.get_pixel(x, y + 1) == lower {
+                    continue;
+                }
+
+                if cursor != Some((x, row)) {
+                    frame.write_all(b"\x1b[")?;
+                    frame.write_all(buffer.format(row + 1).as_bytes())?;
+                    frame.write_u8(b';')?;
+                    frame.write_all(buffer.format(x + 1).as_bytes())?;
+                    frame.write_u8(b'H')?;
+                }
+
+                if last_upper.is_none_or(|v| v != upper) {
+                    upper.fg_code(frame)?;
+                }
+
+                if last_lower.is_none_or(|v| v != lower) {
+                    lower.bg_code(frame)?;
+                }
+
+                frame.write_all(b"\xE2\x96\x80")?;
+
+                cursor = Some((x + 1, row));
+                last_upper = Some(upper);
+                last_lower = Some(lower);
+            }
+        }
+
+        Ok(())
+    }
+
     fn est_size(&self) -> Option<usize> {
         Some(self.width() as usize * self.height() as usize * 20)
     }
 }
+
+pub trait CellPixel {
+    const COLOR: ColorMode;
+
+    fn write_cell_bytes(&self, out: &mut Vec<u8>);
+}
+
+impl CellPixel for Luma<u8> {
+    const COLOR: ColorMode = ColorMode::EightBit;
+
+    fn write_cell_bytes(&self, out: &mut Vec<u8>) {
+        out.push(self.0[0]);
+    }
+}
+
+impl CellPixel for Rgb<u8> {
+    const COLOR: ColorMode = ColorMode::Full;
+
+    fn write_cell_bytes(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.0);
+    }
+}
+
+/// Converts an image into a binary half-block cell grid, leaving escape generation to playback time.
+pub trait ToCells {
+    fn to_cells(&self) -> CellFrame;
+}
+
+impl<T> ToCells for T
+where
+    T: GenericImageView<Pixel: CellPixel>,
+{
+    fn to_cells(&self) -> CellFrame {
+        let mut frame = CellFrame::new(
+            self.width() as u16,
+            (self.height() / 2) as u16,
+            T::Pixel::COLOR,
+        );
+
+        for y in (0..self.height() - 1).step_by(2) {
+            for x in 0..self.width() {
+                self.get_pixel(x, y).write_cell_bytes(&mut frame.cells);
+                self.get_pixel(x, y + 1).write_cell_bytes(&mut frame.cells);
+            }
+        }
+
+        frame
+    }
+}
@@ -0,0 +1,739 @@
+use std::{
+    io::{self, SeekFrom},
+    marker::PhantomData,
+};
+
+use container::{
+    Error, Packet, PacketDataType,
+    demux::{Demuxer, ReadBuffer, Step},
+    index::PacketIndex,
+    limits::DecodeLimits,
+    metadata::{FormatData, TimeBase},
+    preamble::Preamble,
+    seek::SeekEntry,
+};
+use litemap::LiteMap;
+use thingbuf::{mpsc, recycling::WithCapacity};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+
+use crate::{
+    DamageReport, Decoders, PacketWithData, READ_CHUNK, create_decoders, decompress_packet,
+    ends_after, packet_capacity, processors::KeyRing, read_header_streaming, states, verify_packet,
+};
+
+/// `Reader` for async input. It drives the same `Demuxer` through the same states (header, seek tables, packets),
+/// everything but the IO works like it does there.
+pub struct AsyncReader<R, S> {
+    reader: R,
+    input: ReadBuffer,
+    demux: Demuxer,
+    scratch: Vec<u8>,
+    decoders: Decoders,
+    keys: KeyRing,
+    recover: bool,
+    damage: Vec<DamageReport>,
+    _spooky: PhantomData<S>,
+}
+
+impl<R: AsyncRead + Unpin, S> AsyncReader<R, S> {
+    /// format version and layout, `None` for legacy (v3.0) files
+    pub fn preamble(&self) -> Option<&Preamble> {
+        self.demux.preamble()
+    }
+
+    /// see `Reader::set_limits`
+    pub fn set_limits(&mut self, limits: DecodeLimits) {
+        self.demux.set_limits(limits);
+    }
+
+    pub fn limits(&self) -> &DecodeLimits {
+        self.demux.limits()
+    }
+
+    /// see `Reader::set_keys`
+    pub fn set_keys(&mut self, keys: KeyRing) {
+        self.keys = keys;
+    }
+
+    /// see `Reader::time_base`
+    pub fn time_base(&self, stream: u8) -> TimeBase {
+        self.demux.time_base(stream)
+    }
+
+    // buffers at least `len` bytes, unless the input ends first
+    async fn fill(&mut self, len: usize) -> io::Result<()> {
+        while self.input.available().len() < len && !self.input.is_eof() {
+            let wanted = READ_CHUNK.max(len - self.input.available().len());
+            let read = self.reader.read(self.input.spare(wanted)).await?;
+            self.input.filled(read);
+        }
+
+        Ok(())
+    }
+
+    // moves the input to where the demuxer goes on, that has to be buffered
+    fn move_input(&mut self) -> io::Result<()> {
+        if self.input.move_to(self.demux.position()) {
+            return Ok(());
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "input can't seek",
+        ))
+    }
+
+    // runs `step` on the input until it's done
+    async fn drive<T>(
+        &mut self,
+        mut step: impl FnMut(&mut Demuxer, &[u8], bool) -> container::Result<Step<T>>,
+    ) -> container::Result<T> {
+        loop {
+            self.move_input()?;
+
+            match step(&mut self.demux, self.input.available(), self.input.is_eof())? {
+                Step::Done(value) => {
+                    self.move_input()?;
+                    return Ok(value);
+                }
+                Step::Needs(len) => self.fill(len).await?,
+                Step::Moved => {}
+            }
+        }
+    }
+
+    fn into_state<T>(self) -> AsyncReader<R, T> {
+        AsyncReader {
+            reader: self.reader,
+            input: self.input,
+            demux: self.demux,
+            scratch: self.scratch,
+            decoders: self.decoders,
+            keys: self.keys,
+            recover: self.recover,
+            damage: self.damage,
+            _spooky: PhantomData,
+        }
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin, S> AsyncReader<R, S> {
+    // `move_input`, seeking there if it's not buffered
+    async fn seek_input(&mut self) -> io::Result<()> {
+        let position = self.demux.position();
+        if !self.input.move_to(position) {
+            self.reader.seek(SeekFrom::Start(position)).await?;
+            self.input.reset(position);
+        }
+
+        Ok(())
+    }
+
+    // `drive` for steps that move around the file, the input follows the demuxer even if it fails
+    async fn drive_seeking<T>(
+        &mut self,
+        mut step: impl FnMut(&mut Demuxer, &[u8], bool) -> container::Result<Step<T>>,
+    ) -> container::Result<T> {
+        let result = loop {
+            self.seek_input().await?;
+
+            match step(&mut self.demux, self.input.available(), self.input.is_eof()) {
+                Ok(Step::Done(value)) => break Ok(value),
+                Ok(Step::Needs(len)) => self.fill(len).await?,
+                Ok(Step::Moved) => {}
+                Err(e) => break Err(e),
+            }
+        };
+
+        self.seek_input().await?;
+        result
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncReader<R, states::Start> {
+    pub fn new(reader: R) -> AsyncReader<R, states::Start> {
+        AsyncReader {
+            reader,
+            input: ReadBuffer::new(),
+            demux: Demuxer::new(),
+            scratch: Vec::new(),
+            decoders: LiteMap::new(),
+            keys: KeyRing::default(),
+            recover: false,
+            damage: Vec::new(),
+            _spooky: PhantomData,
+        }
+    }
+
+    /// see `Reader::read_header_streaming`
+    pub async fn read_header_streaming(
+        mut self,
+    ) -> container::Result<(AsyncReader<R, states::HeaderRead>, FormatData)> {
+        let read = self.drive(read_header_streaming).await;
+        self.with_header(read)
+    }
+
+    fn with_header(
+        mut self,
+        read: container::Result<(FormatData, u64)>,
+    ) -> container::Result<(AsyncReader<R, states::HeaderRead>, FormatData)> {
+        let (header, offset) = read?;
+        self.decoders = create_decoders(&header, &self.keys, offset)?;
+        self.scratch = Vec::with_capacity(packet_capacity(&header, self.demux.limits()));
+
+        Ok((self.into_state(), header))
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncReader<R, states::Start> {
+    pub async fn read_header(
+        mut self,
+    ) -> container::Result<(AsyncReader<R, states::HeaderRead>, FormatData)> {
+        let read = self.drive_seeking(Demuxer::read_header).await;
+        self.with_header(read)
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncReader<R, states::HeaderRead> {
+    /// see `Reader::read_seektables_streaming`
+    pub async fn read_seektables_streaming(
+        mut self,
+    ) -> container::Result<(
+        AsyncReader<R, states::SeektablesRead>,
+        Vec<(u8, Vec<SeekEntry>)>,
+    )> {
+        let seektables = self.drive(Demuxer::read_seektables).await?;
+
+        Ok((self.into_state(), seektables))
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncReader<R, states::HeaderRead> {
+    pub async fn read_seektables(
+        mut self,
+    ) -> container::Result<(
+        AsyncReader<R, states::SeektablesRead>,
+        Vec<(u8, Vec<SeekEntry>)>,
+    )> {
+        let seektables = self.drive_seeking(Demuxer::read_seektables).await?;
+
+        Ok((self.into_state(), seektables))
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncReader<R, states::SeektablesRead> {
+    /// see `Reader::seek`
+    pub async fn seek(&mut self, stream: u8, time: i64) -> container::Result<i64> {
+        self.drive_seeking(|demux, input, eof| demux.seek(stream, time, input, eof))
+            .await
+    }
+
+    /// see `Reader::index`
+    pub async fn index(&mut self) -> container::Result<Option<&PacketIndex>> {
+        self.drive_seeking(Demuxer::read_index).await?;
+
+        Ok(self.demux.index())
+    }
+
+    /// see `Reader::read_indexed`
+    pub async fn read_indexed(&mut self, entry: usize) -> container::Result<(Packet, Vec<u8>)> {
+        self.index().await?;
+        self.demux.seek_indexed(entry)?;
+        self.seek_input().await?;
+
+        self.read_packet().await
+    }
+
+    /// see `Reader::read_attachment`
+    pub async fn read_attachment(&mut self, stream: u8) -> container::Result<Vec<u8>> {
+        let checkpoint = self.demux.checkpoint();
+
+        let result = self.find_attachment(stream).await;
+
+        self.demux.restore(checkpoint);
+        self.seek_input().await?;
+
+        result
+    }
+
+    async fn find_attachment(&mut self, stream: u8) -> container::Result<Vec<u8>> {
+        // legacy files predate attachments
+        if self.demux.preamble().is_none() {
+            return Err(Error::NoAttachment { stream });
+        }
+
+        self.demux.rewind();
+        self.seek_input().await?;
+
+        let mut data = Vec::new();
+        loop {
+            let packet = match self.read_packet_data_into(&mut data).await {
+                Err(Error::EndOfStream) => return Err(Error::NoAttachment { stream }),
+                result => result?,
+            };
+
+            if packet.data_type != PacketDataType::Attachment {
+                return Err(Error::NoAttachment { stream });
+            }
+
+            if packet.stream == stream {
+                return Ok(data);
+            }
+        }
+    }
+
+    /// see `Reader::seek_exact`
+    pub async fn seek_exact(
+        &mut self,
+        stream: u8,
+        time: i64,
+        mut preroll: impl FnMut(&Packet, &[u8]),
+    ) -> container::Result<(Packet, Vec<u8>)> {
+        self.seek(stream, time).await?;
+
+        let time_base = self.time_base(stream);
+        let mut data = Vec::new();
+        loop {
+            let packet = self.read_packet_data_into(&mut data).await?;
+
+            if packet.stream == stream && ends_after(&packet, time_base, time) {
+                return Ok((packet, data));
+            }
+
+            preroll(&packet, &data);
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncReader<R, states::SeektablesRead> {
+    /// see `Reader::set_recover`
+    pub fn set_recover(&mut self, recover: bool) {
+        self.recover = recover;
+    }
+
+    pub fn damage(&self) -> &[DamageReport] {
+        &self.damage
+    }
+
+    /// seek points of `stream`, for fragmented files only those of the first fragment
+    pub fn seektable(&self, stream: u8) -> Option<&[SeekEntry]> {
+        self.demux.seektable(stream)
+    }
+
+    async fn try_read_packet(&mut self, data: &mut Vec<u8>) -> container::Result<Packet> {
+        let (offset, mut packet) = self.drive(Demuxer::next_packet).await?;
+
+        self.fill(packet.data_len as usize).await?;
+        data.clear();
+        data.extend_from_slice(
+            self.demux
+                .payload(offset, &packet, self.input.available())?,
+        );
+        self.move_input()?;
+
+        verify_packet(&self.decoders, offset, &packet, data)?;
+        decompress_packet(
+            &mut self.decoders,
+            self.demux.limits(),
+            offset,
+            &mut packet,
+            data,
+        )?;
+
+        Ok(packet)
+    }
+
+    /// Fails with `Error::EndOfStream` once there are no packets left.
+    pub async fn read_packet_data_into(&mut self, data: &mut Vec<u8>) -> container::Result<Packet> {
+        loop {
+            let start = self.demux.position();
+
+            match self.try_read_packet(data).await {
+                Err(e) if self.recover && e.is_damage() => {
+                    self.drive(Demuxer::resync).await?;
+                    self.damage.push(DamageReport {
+                        offset: start,
+                        skipped: self.demux.position() - start,
+                        reason: e.to_string(),
+                    });
+                }
+                result => return result,
+            }
+        }
+    }
+
+    pub async fn read_packet(&mut self) -> container::Result<(Packet, Vec<u8>)> {
+        let mut data = Vec::new();
+        let packet = self.read_packet_data_into(&mut data).await?;
+
+        Ok((packet, data))
+    }
+
+    /// see `Reader::read_packet_into_channel`
+    pub async fn read_packet_into_channel(
+        &mut self,
+        channel: &mpsc::Sender<PacketWithData, WithCapacity>,
+    ) -> container::Result<()> {
+        // read first, so nothing gets sent when the file ends
+        let mut data = std::mem::take(&mut self.scratch);
+        let packet = self.read_packet_data_into(&mut data).await;
+        self.scratch = data;
+
+        let packet = packet?;
+
+        let mut send_slot = channel
+            .send_ref()
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "packet receiver hung up"))?;
+        std::mem::swap(&mut send_slot.data, &mut self.scratch);
+        send_slot.header = packet;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{self, Cursor, Read, Seek, SeekFrom},
+        ops::Range,
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
+
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use container::{
+        EncodableData, Packet, PacketDataType, SYNC_MARKER,
+        header::{HeaderCodec, HeaderVersion},
+        index::{IndexEntry, PacketIndex},
+        metadata::{
+            CodecParameters, CompressionMode, DataEncoding, DataParameters, FormatData, Stream,
+        },
+        preamble::{Layout, Preamble, TRAILER_OFFSET_POSITION, VERSION_MAJOR},
+        seek::{SeekEntry, delta_encode},
+    };
+
+    use crate::{Reader, async_reader::AsyncReader, states};
+
+    const PACKETS: usize = 24;
+
+    // everything there is to read from a file, along with where seeking lands
+    #[derive(Debug, PartialEq)]
+    struct Contents {
+        header: FormatData,
+        seektables: Vec<(u8, Vec<SeekEntry>)>,
+        packets: Vec<(Packet, Vec<u8>)>,
+        seeks: Vec<(i64, Packet)>,
+        index: Option<PacketIndex>,
+    }
+
+    const SEEKS: [i64; 4] = [0, 2500, 9000, 11_000];
+
+    // the packets of the test files, two streams taking turns. stream 0 has a seek point every 4 packets
+    fn packet(i: usize) -> (Packet, Vec<u8>) {
+        let data = vec![i as u8; 10 + i];
+        let packet = Packet {
+            stream: (i % 2) as u8,
+            packet_idx: (i / 2) as u64,
+            timestamp: (i / 2) as u64 * 1000,
+            duration: 1000,
+            data_type: PacketDataType::Data,
+            data_len: data.len() as u64,
+            ..Packet::default()
+        };
+
+        (packet, data)
+    }
+
+    fn is_seek_point(i: usize) -> bool {
+        i.is_multiple_of(8)
+    }
+
+    fn header() -> Vec<u8> {
+        let stream = |index| {
+            Stream::new(
+                format!("data {index}"),
+                index,
+                12_000,
+                Default::default(),
+                None,
+                CompressionMode::None,
+                CodecParameters::Data(DataParameters::new(DataEncoding::Opaque)),
+            )
+        };
+
+        let header = FormatData::new(
+            "test".to_string(),
+            "test".to_string(),
+            vec![stream(0), stream(1)],
+        );
+        rasn::der::encode(&header).unwrap()
+    }
+
+    // packets `range` like the encoder writes them, along with the seek table section for them.
+    // offsets are relative to the first of them
+    fn encode_packets(
+        range: Range<usize>,
+        headers: &mut HeaderCodec,
+        index: &mut PacketIndex,
+    ) -> (Vec<u8>, Vec<u8>) {
+        let mut out = Vec::new();
+        let mut entries = Vec::new();
+        headers.reset();
+
+        for i in range {
+            let (packet, data) = packet(i);
+            if is_seek_point(i) {
+                out.extend_from_slice(&SYNC_MARKER);
+                entries.push(SeekEntry {
+                    ts: packet.timestamp as i64,
+                    location: out.len() as i64 - SYNC_MARKER.len() as i64,
+                });
+                headers.reset();
+            }
+
+            let offset = out.len() as u64;
+            headers.encode_into(&packet, &mut out).unwrap();
+            out.extend_from_slice(&data);
+            index.push(IndexEntry {
+                stream: packet.stream,
+                packet_idx: packet.packet_idx,
+                timestamp: packet.timestamp as i64,
+                offset,
+                size: out.len() as u64 - offset,
+                keyframe: true,
+            });
+        }
+
+        let mut seektables = vec![];
+        if entries.is_empty() {
+            seektables.push(0);
+        } else {
+            let mut encoded = delta_encode(entries.iter().map(|entry| entry.ts));
+            encoded.append(&mut delta_encode(
+                entries.iter().map(|entry| entry.location),
+            ));
+            let compressed = lz4_flex::compress_prepend_size(&encoded);
+
+            seektables.push(1);
+            seektables.push(0);
+            seektables
+                .write_u64::<LittleEndian>(compressed.len() as u64)
+                .unwrap();
+            seektables
+                .write_u64::<LittleEndian>(entries.len() as u64)
+                .unwrap();
+            seektables.extend_from_slice(&compressed);
+        }
+
+        (seektables, out)
+    }
+
+    fn with_header(out: &mut Vec<u8>, header: &[u8]) {
+        out.write_u64::<LittleEndian>(header.len() as u64).unwrap();
+        out.extend_from_slice(header);
+    }
+
+    // header + seek tables at the end, with a packet index
+    fn trailer_file() -> Vec<u8> {
+        let mut headers = HeaderCodec::new(HeaderVersion::for_format_version(VERSION_MAJOR));
+        let mut index = PacketIndex::new();
+        let mut out = Vec::new();
+        Preamble::new(Layout::Trailer { trailer_offset: 0 })
+            .encode_into(&mut out)
+            .unwrap();
+
+        let (seektables, packets) = encode_packets(0..PACKETS, &mut headers, &mut index);
+        out.extend_from_slice(&packets);
+
+        let trailer_offset = out.len() as u64;
+        with_header(&mut out, &header());
+        out.extend_from_slice(&seektables);
+        index.encode_into(&mut out).unwrap();
+
+        let at = TRAILER_OFFSET_POSITION as usize;
+        out[at..at + 8].copy_from_slice(&trailer_offset.to_le_bytes());
+        out
+    }
+
+    // three fragments, each with its own header and seek tables
+    fn fragmented_file() -> Vec<u8> {
+        let mut headers = HeaderCodec::new(HeaderVersion::for_format_version(VERSION_MAJOR));
+        let mut out = Vec::new();
+        Preamble::new(Layout::Fragmented)
+            .encode_into(&mut out)
+            .unwrap();
+
+        for start in (0..PACKETS).step_by(8) {
+            let (seektables, packets) =
+                encode_packets(start..start + 8, &mut headers, &mut PacketIndex::new());
+
+            with_header(&mut out, &header());
+            out.extend_from_slice(&seektables);
+            out.write_u64::<LittleEndian>(packets.len() as u64).unwrap();
+            out.extend_from_slice(&packets);
+        }
+
+        out
+    }
+
+    // no preamble, v1 packet headers
+    fn legacy_file() -> Vec<u8> {
+        let mut headers = HeaderCodec::new(HeaderVersion::V1);
+        let (seektables, packets) =
+            encode_packets(0..PACKETS, &mut headers, &mut PacketIndex::new());
+
+        let mut out = Vec::new();
+        with_header(&mut out, &header());
+        out.extend_from_slice(&seektables);
+        out.extend_from_slice(&packets);
+        out
+    }
+
+    // reads a byte at a time, so the demuxer has to ask for more all the time
+    struct Trickle<R>(R);
+
+    impl<R: Read> Read for Trickle<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    impl<R: Seek> Seek for Trickle<R> {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.0.seek(pos)
+        }
+    }
+
+    fn read_sync(reader: impl Read + Seek) -> Contents {
+        let (reader, header) = Reader::new(reader).read_header().unwrap();
+        let (mut reader, seektables) = reader.read_seektables().unwrap();
+        let packets = read_packets(&mut reader);
+
+        let seeks = SEEKS
+            .iter()
+            .map(|&time| {
+                (
+                    reader.seek(0, time).unwrap(),
+                    reader.read_packet().unwrap().0,
+                )
+            })
+            .collect();
+        let index = reader.index().unwrap().cloned();
+
+        Contents {
+            header,
+            seektables,
+            packets,
+            seeks,
+            index,
+        }
+    }
+
+    fn read_packets<R: Read>(
+        reader: &mut Reader<R, states::SeektablesRead>,
+    ) -> Vec<(Packet, Vec<u8>)> {
+        std::iter::from_fn(|| reader.read_packet().ok()).collect()
+    }
+
+    fn read_async(file: Vec<u8>) -> Contents {
+        block_on(async {
+            let reader = AsyncReader::new(Cursor::new(file));
+            let (reader, header) = reader.read_header().await.unwrap();
+            let (mut reader, seektables) = reader.read_seektables().await.unwrap();
+
+            let mut packets = Vec::new();
+            while let Ok(packet) = reader.read_packet().await {
+                packets.push(packet);
+            }
+
+            let mut seeks = Vec::new();
+            for time in SEEKS {
+                let at = reader.seek(0, time).await.unwrap();
+                seeks.push((at, reader.read_packet().await.unwrap().0));
+            }
+            let index = reader.index().await.unwrap().cloned();
+
+            Contents {
+                header,
+                seektables,
+                packets,
+                seeks,
+                index,
+            }
+        })
+    }
+
+    // the test files are in memory, nothing ever has to wait
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    #[test]
+    fn test_reads_like_reader() {
+        let expected: Vec<_> = (0..PACKETS).map(packet).collect();
+
+        for (file, has_index) in [
+            (trailer_file(), true),
+            (fragmented_file(), false),
+            (legacy_file(), false),
+        ] {
+            let contents = read_sync(Cursor::new(file.clone()));
+            assert_eq!(contents.packets, expected);
+            assert_eq!(contents.index.is_some(), has_index);
+            assert_eq!(
+                contents
+                    .seeks
+                    .iter()
+                    .map(|(at, packet)| (*at, packet.packet_idx))
+                    .collect::<Vec<_>>(),
+                [(0, 0), (0, 0), (8000, 8), (8000, 8)]
+            );
+
+            assert_eq!(read_sync(Trickle(Cursor::new(file.clone()))), contents);
+            assert_eq!(read_async(file), contents);
+        }
+    }
+
+    #[test]
+    fn test_streaming() {
+        for file in [fragmented_file(), legacy_file()] {
+            let (reader, header) = Reader::new(file.as_slice())
+                .read_header_streaming()
+                .unwrap();
+            let (mut reader, seektables) = reader.read_seektables_streaming().unwrap();
+            let packets = read_packets(&mut reader);
+
+            let (header_async, seektables_async, packets_async) = block_on(async {
+                let reader = AsyncReader::new(file.as_slice());
+                let (reader, header) = reader.read_header_streaming().await.unwrap();
+                let (mut reader, seektables) = reader.read_seektables_streaming().await.unwrap();
+
+                let mut packets = Vec::new();
+                while let Ok(packet) = reader.read_packet().await {
+                    packets.push(packet);
+                }
+
+                (header, seektables, packets)
+            });
+
+            assert_eq!(header_async, header);
+            assert_eq!(seektables_async, seektables);
+            assert_eq!(packets_async, packets);
+            assert_eq!(packets.len(), PACKETS);
+        }
+
+        assert!(
+            Reader::new(trailer_file().as_slice())
+                .read_header_streaming()
+                .is_err()
+        );
+    }
+}
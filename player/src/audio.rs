@@ -0,0 +1,230 @@
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use container::metadata::AudioParameters;
+use parking_lot::Mutex;
+
+use crate::renderer::{PlayThreadState, RendererState};
+
+// how far the audio cursor may drift from the video clock before we drop samples / insert silence
+const DRIFT_TOLERANCE: Duration = Duration::from_millis(40);
+
+struct AudioChunk {
+    starts_at: Duration,
+    samples: Vec<i16>,
+    consumed: usize,
+}
+
+/// Queue of decoded pcm audio for one stream, shared between the render loop (producer) and the audio output (consumer).
+#[derive(Clone)]
+pub struct AudioSink {
+    pub stream: u8,
+    sample_rate: u32,
+    channels: usize,
+    queue: Arc<Mutex<VecDeque<AudioChunk>>>,
+}
+
+impl AudioSink {
+    pub fn new(stream: u8, params: &AudioParameters) -> AudioSink {
+        AudioSink {
+            stream,
+            sample_rate: params.sample_rate,
+            channels: params.channels.max(1) as usize,
+            queue: Arc::new(Mutex::new(VecDeque::with_capacity(64))),
+        }
+    }
+
+    /// queue a packet of interleaved s16le samples starting at `timestamp`
+    pub fn push(&self, timestamp: Duration, data: &[u8]) {
+        let samples = data
+            .chunks_exact(2)
+            .map(|v| i16::from_le_bytes([v[0], v[1]]))
+            .collect();
+
+        self.queue.lock().push_back(AudioChunk {
+            starts_at: timestamp,
+            samples,
+            consumed: 0,
+        });
+    }
+
+    pub fn clear(&self) {
+        self.queue.lock().clear();
+    }
+
+    fn frames_to_duration(&self, frames: usize) -> Duration {
+        Duration::from_secs_f64(frames as f64 / self.sample_rate as f64)
+    }
+
+    fn duration_to_frames(&self, duration: Duration) -> usize {
+        (duration.as_secs_f64() * self.sample_rate as f64).round() as usize
+    }
+
+    /// Fill `out` with the samples that should be heard at `plays_at`, following the same clock as the render loop:
+    /// media time `t` is presented at `state.current_time + t`.
+    pub fn fill(&self, out: &mut [f32], plays_at: Instant, state: &RendererState) {
+        if *state.play_status.0.lock() != PlayThreadState::Playing {
+            out.fill(0.0);
+            return;
+        }
+
+        let start = *state.current_time.lock();
+        let mut target = plays_at.saturating_duration_since(start);
+
+        let mut queue = self.queue.lock();
+        let mut written = 0;
+
+        while written < out.len() {
+            let Some(chunk) = queue.front_mut() else {
+                break;
+            };
+
+            let remaining = chunk.samples.len() - chunk.consumed;
+            if remaining == 0 {
+                queue.pop_front();
+                continue;
+            }
+
+            let cursor = chunk.starts_at + self.frames_to_duration(chunk.consumed / self.channels);
+
+            // audio is late: skip ahead to catch up with the video clock
+            if cursor + DRIFT_TOLERANCE < target {
+                let behind = self.duration_to_frames(target - cursor) * self.channels;
+                chunk.consumed += behind.min(remaining);
+                continue;
+            }
+
+            // audio is early: pad with silence until its timestamp comes up
+            if cursor > target + DRIFT_TOLERANCE {
+                let ahead = self.duration_to_frames(cursor - target) * self.channels;
+                let silence = ahead.min(out.len() - written);
+                out[written..written + silence].fill(0.0);
+                written += silence;
+                target += self.frames_to_duration(silence / self.channels);
+                continue;
+            }
+
+            let n = remaining.min(out.len() - written);
+            for (out, sample) in out[written..written + n]
+                .iter_mut()
+                .zip(&chunk.samples[chunk.consumed..chunk.consumed + n])
+            {
+                *out = *sample as f32 / 32768.0;
+            }
+
+            written += n;
+            chunk.consumed += n;
+            target += self.frames_to_duration(n / self.channels);
+        }
+
+        out[written..].fill(0.0);
+    }
+}
+
+#[cfg(feature = "audio")]
+pub struct AudioOutput {
+    _stream: cpal::Stream,
+}
+
+#[cfg(feature = "audio")]
+impl AudioOutput {
+    pub fn new(sink: AudioSink, state: RendererState) -> anyhow::Result<AudioOutput> {
+        use cpal::{
+            SampleFormat, SampleRate,
+            traits::{DeviceTrait, HostTrait, StreamTrait},
+        };
+
+        // most preferred first, samples get converted from f32 for the others
+        const FORMATS: [SampleFormat; 4] = [
+            SampleFormat::F32,
+            SampleFormat::I16,
+            SampleFormat::I32,
+            SampleFormat::U16,
+        ];
+
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or_else(|| anyhow::anyhow!("no audio output device available"))?;
+
+        let sample_rate = SampleRate(sink.sample_rate);
+        let supported = device
+            .supported_output_configs()?
+            .filter(|range| {
+                range.channels() as usize == sink.channels
+                    && (range.min_sample_rate()..=range.max_sample_rate()).contains(&sample_rate)
+            })
+            .filter_map(|range| {
+                let rank = FORMATS.iter().position(|&f| f == range.sample_format())?;
+                Some((rank, range))
+            })
+            .min_by_key(|(rank, _)| *rank)
+            .map(|(_, range)| range.with_sample_rate(sample_rate))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "audio output doesn't support {} channels at {} Hz",
+                    sink.channels,
+                    sink.sample_rate
+                )
+            })?;
+
+        let config = supported.config();
+        let stream = match supported.sample_format() {
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, sink, state)?,
+            SampleFormat::I32 => build_stream::<i32>(&device, &config, sink, state)?,
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, sink, state)?,
+            _ => build_stream::<f32>(&device, &config, sink, state)?,
+        };
+
+        stream.play()?;
+
+        Ok(AudioOutput { _stream: stream })
+    }
+}
+
+#[cfg(feature = "audio")]
+fn build_stream<T: cpal::SizedSample + cpal::FromSample<f32>>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    sink: AudioSink,
+    state: RendererState,
+) -> anyhow::Result<cpal::Stream> {
+    use cpal::traits::DeviceTrait;
+
+    // the sink mixes in f32, only grows until it fits the device's buffer size
+    let mut scratch: Vec<f32> = Vec::new();
+
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+            let timestamp = info.timestamp();
+            let latency = timestamp
+                .playback
+                .duration_since(&timestamp.callback)
+                .unwrap_or_default();
+
+            scratch.resize(data.len(), 0.0);
+            sink.fill(&mut scratch, Instant::now() + latency, &state);
+
+            for (out, sample) in data.iter_mut().zip(&scratch) {
+                *out = T::from_sample(*sample);
+            }
+        },
+        |_| {},
+        None,
+    )?;
+
+    Ok(stream)
+}
+
+#[cfg(not(feature = "audio"))]
+pub struct AudioOutput;
+
+#[cfg(not(feature = "audio"))]
+impl AudioOutput {
+    pub fn new(_sink: AudioSink, _state: RendererState) -> anyhow::Result<AudioOutput> {
+        anyhow::bail!("player was built without audio support")
+    }
+}
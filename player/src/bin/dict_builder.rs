@@ -1,14 +1,10 @@
-use std::{
-    fs::File,
-    io::{BufReader, Write},
-    path::PathBuf,
-};
+use std::{io::Write, path::PathBuf};
 
 use clap::Parser;
 use container::PacketDataType;
 use humansize::format_size;
 use parse_size::parse_size;
-use player::Reader;
+use player::mmap::MmapReader;
 
 #[derive(clap::Parser, Debug, Clone)]
 #[command()]
@@ -26,22 +22,17 @@ struct DictArgs {
 fn main() -> anyhow::Result<()> {
     let args = DictArgs::parse();
 
-    let input = BufReader::new(File::open(args.input)?);
-
-    let reader = Reader::new(input);
-
-    let (reader, _) = reader.read_header()?;
-    let (mut reader, _) = reader.read_seektables()?;
+    let mut reader = MmapReader::open(args.input)?;
 
     let mut data_buffer = Vec::with_capacity(args.mem_usage);
     let mut data_sizes = Vec::with_capacity(256_000);
 
-    while let Ok((packet_header, mut data)) = reader.read_packet() {
+    while let Ok(packet) = reader.next_packet() {
         if data_buffer.len() >= args.mem_usage {
             break;
         }
 
-        if packet_header.data_type != PacketDataType::Video {
+        if packet.header.data_type != PacketDataType::Video {
             continue;
         }
 
@@ -52,8 +43,8 @@ fn main() -> anyhow::Result<()> {
         );
         std::io::stdout().flush()?;
 
-        data_sizes.push(data.len());
-        data_buffer.append(&mut data);
+        data_sizes.push(packet.data.len());
+        data_buffer.extend_from_slice(packet.data);
     }
 
     println!();
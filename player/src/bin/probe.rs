@@ -1,18 +1,21 @@
 use std::{
     fs::File,
     io::{BufReader, BufWriter, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
     time::Duration,
 };
 
 use clap::Parser;
-use container::{EncodableData, PacketDataType, SubRect, SubRectVec};
+use container::{Error, PacketDataType, SubRect, SubRectVec, limits::DecodeLimits};
 use player::{FormatDuration, Reader};
 
 #[derive(clap::Parser)]
 struct ProbeArgs {
     #[arg(long)]
     seektables: Option<PathBuf>,
+    /// write every entry of the packet index to this file
+    #[arg(long)]
+    index: Option<PathBuf>,
     #[arg(long)]
     inspect_packets: bool,
     #[arg(long)]
@@ -21,6 +24,15 @@ struct ProbeArgs {
     extract_header_xer: Option<PathBuf>,
     #[arg(long)]
     extract_header_der: Option<PathBuf>,
+    /// write every attachment into this directory, under its own file name
+    #[arg(long)]
+    extract_attachments: Option<PathBuf>,
+    /// skip damaged packets instead of stopping at the first one
+    #[arg(long)]
+    recover: bool,
+    /// trust sizes read from the file, for files that go over the default decode limits
+    #[arg(long)]
+    no_limits: bool,
     input: PathBuf,
 }
 
@@ -29,11 +41,75 @@ fn main() -> anyhow::Result<()> {
 
     let input = BufReader::new(File::open(&cli.input)?);
 
-    let reader = Reader::new(input);
+    let mut reader = Reader::new(input);
+    if cli.no_limits {
+        reader.set_limits(DecodeLimits::unlimited());
+    }
 
     let (reader, header) = reader.read_header()?;
+
+    match reader.preamble() {
+        Some(preamble) => println!(
+            "Format version: {}.{} ({:?})",
+            preamble.major, preamble.minor, preamble.layout
+        ),
+        None => println!("Format version: 3.0 (legacy)"),
+    }
+
     println!("Header: \n{header:#?}");
 
+    if !header.tags().is_empty() {
+        println!("Tags:");
+        for tag in header.tags() {
+            println!("\t{} = {}", tag.key, tag.value);
+        }
+    }
+
+    for stream in &header.tracks {
+        if stream.tags().is_empty() {
+            continue;
+        }
+
+        println!("Tags <-> Stream {} ({}):", stream.index, stream.name);
+        for tag in stream.tags() {
+            println!("\t{} = {}", tag.key, tag.value);
+        }
+    }
+
+    if !header.chapters().is_empty() {
+        println!("Chapters:");
+        for chapter in header.chapters() {
+            println!(
+                "\t{} - {} {}",
+                FormatDuration(Duration::from_micros(chapter.start)),
+                FormatDuration(Duration::from_micros(chapter.end)),
+                chapter.title
+            );
+        }
+    }
+
+    match header.max_interleave() {
+        Some(max) => println!("Max interleave: {}", FormatDuration(max)),
+        None => println!("Max interleave: unknown"),
+    }
+
+    for stream in &header.tracks {
+        let Some(stats) = stream.stats else {
+            continue;
+        };
+
+        println!(
+            "Stats <-> Stream {} ({}): {} packets, {} bytes, avg {} / max {} bytes per packet ({} decompressed)",
+            stream.index,
+            stream.name,
+            stats.frames,
+            stats.total_bytes,
+            stats.avg_packet_size(),
+            stats.max_packet_size,
+            stats.max_decompressed_size
+        );
+    }
+
     if let Some(path) = cli.extract_header_xer {
         std::fs::write(path, rasn::xer::encode(&header).unwrap())?;
     }
@@ -52,35 +128,124 @@ fn main() -> anyhow::Result<()> {
         for (stream, seektable) in seektables {
             writeln!(seektable_debug, "Seek Table <-> Stream {stream}")?;
 
+            let time_base = header.time_base(stream);
             for row in seektable {
                 writeln!(
                     seektable_debug,
                     "{} -> byte {}",
-                    FormatDuration(Duration::from_micros(row.ts as u64)),
+                    FormatDuration(time_base.to_duration(row.ts as u64)),
                     row.location
                 )?;
             }
         }
     }
 
+    match reader.index()? {
+        Some(index) => println!("Packet index: {} packets", index.len()),
+        None => println!("Packet index: none"),
+    }
+
+    let attachments: Vec<_> = header
+        .tracks
+        .iter()
+        .filter_map(|stream| Some((stream.index, stream.parameters.as_attachment()?)))
+        .collect();
+
+    if !attachments.is_empty() {
+        println!("Attachments:");
+    }
+
+    for (stream, attachment) in attachments {
+        let data = match reader.read_attachment(stream) {
+            Ok(data) => data,
+            Err(e) => {
+                println!("	{stream}: {} ({e})", attachment.filename);
+                continue;
+            }
+        };
+
+        println!(
+            "	{stream}: {} ({}, {} bytes){}",
+            attachment.filename,
+            attachment.mime_type,
+            data.len(),
+            if attachment.is_cover() {
+                " [cover]"
+            } else {
+                ""
+            }
+        );
+
+        if let Some(dir) = cli.extract_attachments.as_ref() {
+            // only the name, whatever directories the file might claim
+            let name = Path::new(&attachment.filename)
+                .file_name()
+                .map(|name| name.to_owned())
+                .unwrap_or_else(|| format!("attachment-{stream}").into());
+
+            std::fs::create_dir_all(dir)?;
+            std::fs::write(dir.join(name), &data)?;
+        }
+    }
+
+    if let Some(index_path) = cli.index
+        && let Some(index) = reader.index()?
+    {
+        println!("Writing packet index to {}...", index_path.display());
+
+        let mut index_debug = BufWriter::new(File::create(index_path)?);
+        for entry in index.entries() {
+            writeln!(
+                index_debug,
+                "stream {} #{} @ {} -> byte {} ({} bytes){}",
+                entry.stream,
+                entry.packet_idx,
+                FormatDuration(
+                    header
+                        .time_base(entry.stream)
+                        .to_duration(entry.timestamp as u64)
+                ),
+                entry.offset,
+                entry.size,
+                if entry.keyframe { " [keyframe]" } else { "" }
+            )?;
+        }
+    }
+
     if !cli.inspect_packets && !cli.debug_subtitles {
         return Ok(());
     }
 
-    while let Ok((packet_header, data)) = reader.read_packet() {
+    reader.set_recover(cli.recover);
+
+    loop {
+        let (packet_header, data) = match reader.read_packet() {
+            Ok(packet) => packet,
+            Err(Error::EndOfStream) => break,
+            Err(e) => {
+                println!("Stopped reading packets: {e}");
+                break;
+            }
+        };
+
         if cli.inspect_packets {
             println!("{packet_header}");
         }
 
         if cli.debug_subtitles && packet_header.data_type == PacketDataType::Subtitle {
             println!("Subtitles for stream {} ->", packet_header.stream);
-            let new_subs: Vec<SubRect> = SubRectVec::decode_from(&mut data.as_slice())
-                .unwrap()
-                .into_inner();
+            let new_subs: Vec<SubRect> =
+                SubRectVec::decode_with_limits(&mut data.as_slice(), reader.limits())
+                    .unwrap()
+                    .into_inner();
             println!("{new_subs:#?}");
         }
     }
 
+    for damage in reader.damage() {
+        println!("{damage}");
+    }
+
     // let reader = std::thread::spawn(move || -> anyhow::Result<()> {
 
     //     while let Ok(packet_header) = Packet::decode_from(&mut input) {
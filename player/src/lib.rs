@@ -2,23 +2,31 @@
 
 use std::{
     fmt::Display,
-    io::{Cursor, Read, Seek},
+    io::{self, Read, Seek, SeekFrom},
     marker::PhantomData,
     time::Duration,
 };
 
-use byteorder::{LittleEndian, ReadBytesExt};
 use container::{
-    EncodableData, Packet,
-    metadata::{CompressionMode, FormatData},
-    seek::{SeekEntry, delta_decode},
+    Error, Packet, PacketDataType,
+    demux::{Demuxer, ReadBuffer, Step},
+    index::PacketIndex,
+    limits::{DecodeLimits, Limit},
+    metadata::{CompressionMode, FormatData, TimeBase, features},
+    preamble::{Layout, Preamble},
+    seek::SeekEntry,
+    side_data,
 };
 use litemap::LiteMap;
 use thingbuf::{Recycle, mpsc, recycling::WithCapacity};
 use tsz_compress::prelude::TszDecompressV2;
 
-use crate::processors::{DecoderProcessor, Lz4Decoder, ZstdDecoder};
+use crate::processors::{DecoderProcessor, Decryptor, KeyRing, Lz4Decoder, ZstdDecoder};
 
+#[cfg(feature = "tokio")]
+pub mod async_reader;
+pub mod audio;
+pub mod mmap;
 pub mod processors;
 pub mod renderer;
 
@@ -59,185 +67,539 @@ pub mod states {
     pub struct SeektablesRead;
 }
 
+// packet buffers for files without stream stats (before 6.4), a 192x108 frame fits
+const DEFAULT_PACKET_CAPACITY: u64 = 192 * 108 * 20;
+
+// how much to read at once, the demuxer asks for more when that's not enough
+const READ_CHUNK: usize = 64 << 10;
+
+/// What to reserve for a buffer holding any packet of the file, from the stream stats in its header.
+/// The stats aren't trusted past `DecodeLimits::max_packet_len`, bigger packets still fail when read.
+pub fn packet_capacity(header: &FormatData, limits: &DecodeLimits) -> usize {
+    header
+        .max_decompressed_size()
+        .unwrap_or(DEFAULT_PACKET_CAPACITY)
+        .min(limits.max_packet_len) as usize
+}
+
+/// A stretch of the file that couldn't be read and was skipped while recovering.
+#[derive(Debug, Clone)]
+pub struct DamageReport {
+    /// where the damaged packet started
+    pub offset: u64,
+    /// bytes skipped until the next sync marker (or the end of the packet data)
+    pub skipped: u64,
+    pub reason: String,
+}
+
+impl Display for DamageReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "damage at byte {}: skipped {} bytes ({})",
+            self.offset, self.skipped, self.reason
+        )
+    }
+}
+
 pub struct Reader<R: Read, S> {
     reader: R,
+    input: ReadBuffer,
+    demux: Demuxer,
     scratch: Vec<u8>,
-    decoders: LiteMap<u8, Box<dyn DecoderProcessor + Send>>,
-    seektable: Vec<SeekEntry>,
-    start_of_packets: u64,
+    decoders: Decoders,
+    keys: KeyRing,
+    recover: bool,
+    damage: Vec<DamageReport>,
     last_time: i64,
     _spooky: PhantomData<S>,
 }
 
-impl<R: Read + Seek> Reader<R, states::Start> {
+impl<R: Read, S> Reader<R, S> {
+    /// format version and layout, `None` for legacy (v3.0) files
+    pub fn preamble(&self) -> Option<&Preamble> {
+        self.demux.preamble()
+    }
+
+    /// Sizes read from the file are checked against these before allocating anything,
+    /// going over one fails with `Error::LimitExceeded`.
+    pub fn set_limits(&mut self, limits: DecodeLimits) {
+        self.demux.set_limits(limits);
+    }
+
+    pub fn limits(&self) -> &DecodeLimits {
+        self.demux.limits()
+    }
+
+    /// For encrypted files, has to be set before the header is read. Without the keys (or the `encryption`
+    /// feature) their header still reads fine, packets fail with `Error::Decryption`.
+    pub fn set_keys(&mut self, keys: KeyRing) {
+        self.keys = keys;
+    }
+
+    /// unit of `stream`'s packet timestamps and seek tables, microseconds until the header is read
+    pub fn time_base(&self, stream: u8) -> TimeBase {
+        self.demux.time_base(stream)
+    }
+
+    // buffers at least `len` bytes, unless the input ends first
+    fn fill(&mut self, len: usize) -> io::Result<()> {
+        while self.input.available().len() < len && !self.input.is_eof() {
+            let wanted = READ_CHUNK.max(len - self.input.available().len());
+            match self.reader.read(self.input.spare(wanted)) {
+                Ok(read) => self.input.filled(read),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    // moves the input to where the demuxer goes on, that has to be buffered
+    fn move_input(&mut self) -> io::Result<()> {
+        if self.input.move_to(self.demux.position()) {
+            return Ok(());
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "input can't seek",
+        ))
+    }
+
+    // runs `step` on the input until it's done
+    fn drive<T>(
+        &mut self,
+        mut step: impl FnMut(&mut Demuxer, &[u8], bool) -> container::Result<Step<T>>,
+    ) -> container::Result<T> {
+        loop {
+            self.move_input()?;
+
+            match step(&mut self.demux, self.input.available(), self.input.is_eof())? {
+                Step::Done(value) => {
+                    self.move_input()?;
+                    return Ok(value);
+                }
+                Step::Needs(len) => self.fill(len)?,
+                Step::Moved => {}
+            }
+        }
+    }
+
+    fn into_state<T>(self) -> Reader<R, T> {
+        Reader {
+            reader: self.reader,
+            input: self.input,
+            demux: self.demux,
+            scratch: self.scratch,
+            decoders: self.decoders,
+            keys: self.keys,
+            recover: self.recover,
+            damage: self.damage,
+            last_time: self.last_time,
+            _spooky: PhantomData,
+        }
+    }
+}
+
+impl<R: Read + Seek, S> Reader<R, S> {
+    // `move_input`, seeking there if it's not buffered
+    fn seek_input(&mut self) -> io::Result<()> {
+        let position = self.demux.position();
+        if !self.input.move_to(position) {
+            self.reader.seek(SeekFrom::Start(position))?;
+            self.input.reset(position);
+        }
+
+        Ok(())
+    }
+
+    // `drive` for steps that move around the file, the input follows the demuxer even if it fails
+    fn drive_seeking<T>(
+        &mut self,
+        mut step: impl FnMut(&mut Demuxer, &[u8], bool) -> container::Result<Step<T>>,
+    ) -> container::Result<T> {
+        let result = loop {
+            self.seek_input()?;
+
+            match step(&mut self.demux, self.input.available(), self.input.is_eof()) {
+                Ok(Step::Done(value)) => break Ok(value),
+                Ok(Step::Needs(len)) => self.fill(len)?,
+                Ok(Step::Moved) => {}
+                Err(e) => break Err(e),
+            }
+        };
+
+        self.seek_input()?;
+        result
+    }
+}
+
+impl<R: Read> Reader<R, states::Start> {
     pub fn new(reader: R) -> Reader<R, states::Start> {
         Reader {
             reader,
-            scratch: Vec::with_capacity(192 * 108 * 20),
+            input: ReadBuffer::new(),
+            demux: Demuxer::new(),
+            scratch: Vec::new(),
             decoders: LiteMap::new(),
-            seektable: Vec::new(),
-            start_of_packets: 0,
+            keys: KeyRing::default(),
+            recover: false,
+            damage: Vec::new(),
             last_time: 0,
             _spooky: PhantomData,
         }
     }
 
-    pub fn read_header(mut self) -> anyhow::Result<(Reader<R, states::HeaderRead>, FormatData)> {
-        self.scratch.clear();
-        let header_len = self.reader.read_u64::<LittleEndian>()?;
-        self.scratch.resize(header_len as usize, 0);
-        self.reader.read_exact(&mut self.scratch)?;
-        let header = rasn::der::decode::<FormatData>(&self.scratch)?;
+    /// `read_header` for input that can't seek (pipes, stdin). Works for fragmented and legacy files, but not for files with a trailer.
+    pub fn read_header_streaming(
+        mut self,
+    ) -> container::Result<(Reader<R, states::HeaderRead>, FormatData)> {
+        let read = self.drive(read_header_streaming);
+        self.with_header(read)
+    }
 
-        for stream in &header.tracks {
-            match stream.compression_mode {
-                CompressionMode::None => continue,
-                CompressionMode::Zstd => self.decoders.insert(
-                    stream.index,
-                    Box::new(ZstdDecoder::new(stream.compression_dict.as_ref())?),
-                ),
-                CompressionMode::Lz4 => self.decoders.insert(
-                    stream.index,
-                    Box::new(Lz4Decoder::new(stream.compression_dict.as_ref())),
-                ),
-            };
-        }
+    fn with_header(
+        mut self,
+        read: container::Result<(FormatData, u64)>,
+    ) -> container::Result<(Reader<R, states::HeaderRead>, FormatData)> {
+        let (header, offset) = read?;
+        self.decoders = create_decoders(&header, &self.keys, offset)?;
+        self.scratch = Vec::with_capacity(packet_capacity(&header, self.demux.limits()));
 
-        Ok((
-            Reader {
-                start_of_packets: self.reader.stream_position()?,
-                reader: self.reader,
-                scratch: self.scratch,
-                decoders: self.decoders,
-                seektable: Vec::new(),
-                last_time: 0,
-                _spooky: PhantomData,
-            },
-            header,
-        ))
+        Ok((self.into_state(), header))
     }
 }
 
-impl<R: Read + Seek> Reader<R, states::HeaderRead> {
-    pub fn read_seektables(
+impl<R: Read + Seek> Reader<R, states::Start> {
+    pub fn read_header(mut self) -> container::Result<(Reader<R, states::HeaderRead>, FormatData)> {
+        let read = self.drive_seeking(Demuxer::read_header);
+        self.with_header(read)
+    }
+}
+
+impl<R: Read> Reader<R, states::HeaderRead> {
+    /// `read_seektables` for input that can't seek. Fragmented files only return the seek tables of their first fragment.
+    pub fn read_seektables_streaming(
         mut self,
-    ) -> anyhow::Result<(Reader<R, states::SeektablesRead>, Vec<(u8, Vec<SeekEntry>)>)> {
-        let n_seektables = self.reader.read_u8()?;
-        let mut seektables: Vec<(u8, Vec<SeekEntry>)> = Vec::with_capacity(n_seektables as usize);
+    ) -> container::Result<(Reader<R, states::SeektablesRead>, Vec<(u8, Vec<SeekEntry>)>)> {
+        let seektables = self.drive(Demuxer::read_seektables)?;
 
-        for _ in 0..n_seektables {
-            //   let (timestamps, locations): (Vec<i64>, Vec<i64>) = self.entries.into_iter().map(|SeekEntry { ts, location }| (ts, location)).unzip();
+        Ok((self.into_state(), seektables))
+    }
+}
 
-            //         let len_elements = timestamps.len();
+impl<R: Read + Seek> Reader<R, states::HeaderRead> {
+    pub fn read_seektables(
+        mut self,
+    ) -> container::Result<(Reader<R, states::SeektablesRead>, Vec<(u8, Vec<SeekEntry>)>)> {
+        let seektables = self.drive_seeking(Demuxer::read_seektables)?;
 
-            //         let mut encoded = delta_encode(timestamps.into_iter());
-            //         let mut encoded_locations = delta_encode(locations.into_iter());
+        Ok((self.into_state(), seektables))
+    }
+}
 
-            //         encoded.append(&mut encoded_locations);
+// `Demuxer::read_header`, refusing to seek to a trailer
+fn read_header_streaming(
+    demux: &mut Demuxer,
+    input: &[u8],
+    eof: bool,
+) -> container::Result<Step<(FormatData, u64)>> {
+    let step = demux.read_header(input, eof)?;
+
+    if demux
+        .preamble()
+        .is_some_and(|preamble| matches!(preamble.layout, Layout::Trailer { .. }))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "header is stored at the end of the file, it can't be read without seeking",
+        )
+        .into());
+    }
 
-            //         let compressed = lz4_flex::compress_prepend_size(&encoded);
+    Ok(step)
+}
 
-            //         let len_bytes = compressed.len();
+// whether `packet` of `stream` lasts past `time` (microseconds)
+fn ends_after(packet: &Packet, time_base: TimeBase, time: i64) -> bool {
+    let end = packet.timestamp.saturating_add(packet.duration);
+    time_base.to_micros(end.min(i64::MAX as u64) as i64) > time
+}
 
-            //         out.write_u8(self.stream_index).unwrap();
-            //         out.write_u64::<LittleEndian>(len_bytes as u64).unwrap();
-            //         out.write_u64::<LittleEndian>(len_elements as u64).unwrap();
+type Decoders = LiteMap<u8, Box<dyn DecoderProcessor + Send>>;
+
+fn create_decoders(
+    header: &FormatData,
+    keys: &KeyRing,
+    offset: u64,
+) -> container::Result<Decoders> {
+    let mut decoders = Decoders::new();
+    for stream in &header.tracks {
+        match stream.compression_mode {
+            CompressionMode::None => continue,
+            CompressionMode::Zstd => decoders.insert(
+                stream.index,
+                Box::new(
+                    ZstdDecoder::new(stream.compression_dict.as_ref()).map_err(|e| {
+                        Error::HeaderDecode {
+                            offset,
+                            reason: format!(
+                                "stream {}: bad compression dictionary ({e})",
+                                stream.index
+                            ),
+                        }
+                    })?,
+                ),
+            ),
+            CompressionMode::Lz4 => decoders.insert(
+                stream.index,
+                Box::new(Lz4Decoder::new(stream.compression_dict.as_ref())),
+            ),
+        };
+    }
 
-            let stream_index = self.reader.read_u8()?;
+    // payloads get decrypted first, then decompressed
+    if header.features() & features::ENCRYPTED != 0 {
+        for stream in &header.tracks {
+            let next = decoders.remove(&stream.index);
+            decoders.insert(stream.index, Box::new(Decryptor::new(keys, next)));
+        }
+    }
 
-            let len_bytes = self.reader.read_u64::<LittleEndian>()?;
-            let len_elements = self.reader.read_u64::<LittleEndian>()?;
+    Ok(decoders)
+}
 
-            let mut compressed_data = vec![0; len_bytes as usize];
-            self.reader.read_exact(&mut compressed_data)?;
+// checksum, and a compression method the stream can actually decode
+fn verify_packet(
+    decoders: &Decoders,
+    offset: u64,
+    packet: &Packet,
+    data: &[u8],
+) -> container::Result<()> {
+    if packet.verify_checksum(data) == Some(false) {
+        return Err(Error::DamagedPacket {
+            offset,
+            stream: Some(packet.stream),
+            reason: "packet checksum mismatch".to_string(),
+        });
+    }
 
-            let mut data =
-                Cursor::new(lz4_flex::decompress_size_prepended(&compressed_data).unwrap());
+    if !decoders.contains_key(&packet.stream)
+        && let Some(method) = packet.side_data.get(&side_data::COMPRESSION_METHOD)
+        && method.as_slice() != [CompressionMode::None as u8]
+    {
+        return Err(Error::UnknownCompression {
+            offset: Some(offset),
+            stream: packet.stream,
+            method: method.first().copied().unwrap_or_default(),
+        });
+    }
 
-            let timestamps = delta_decode(&mut data, len_elements as usize).unwrap();
-            let locations = delta_decode(&mut data, len_elements as usize).unwrap();
+    Ok(())
+}
 
-            let entries = timestamps
-                .into_iter()
-                .zip(locations.into_iter())
-                .map(|(ts, location)| SeekEntry { ts, location })
-                .collect();
-            seektables.push((stream_index, entries));
-        }
+// replaces `data` with its decompressed contents, for compressed streams
+fn decompress_packet(
+    decoders: &mut Decoders,
+    limits: &DecodeLimits,
+    offset: u64,
+    packet: &mut Packet,
+    data: &mut Vec<u8>,
+) -> container::Result<()> {
+    let Some(decoder) = decoders.get_mut(&packet.stream) else {
+        return Ok(());
+    };
+
+    // repeats are stored without payload, even in compressed streams
+    if packet.data_type == PacketDataType::Repeat {
+        return Ok(());
+    }
 
-        Ok((
-            Reader {
-                start_of_packets: self.reader.stream_position()?,
-                reader: self.reader,
-                scratch: self.scratch,
-                _spooky: PhantomData,
-                seektable: seektables[0].1.clone(),
-                last_time: 0,
-                decoders: self.decoders,
-            },
-            seektables,
-        ))
+    if let Some(len) = packet.side_data.get_u64(&side_data::DECOMPRESSED_LEN) {
+        limits
+            .check(Limit::PacketLen, len)
+            .map_err(|limit| Error::LimitExceeded {
+                offset,
+                stream: Some(packet.stream),
+                limit,
+            })?;
     }
+
+    decoder
+        .process(packet, data)
+        .map_err(|e| e.at_offset(offset))
 }
 
 impl<R: Read + Seek> Reader<R, states::SeektablesRead> {
-    pub fn seek(&mut self, time: i64) -> std::io::Result<i64> {
-        let entry = match self.seektable.binary_search_by_key(&time, |v| v.ts) {
-            Ok(idx) => idx,
-            Err(idx) => idx,
-        };
+    /// Moves to the last seek point of `stream` at or before `time` (falling back to the first one)
+    /// and returns its timestamp, both in microseconds. Packets of every stream are read from there on.
+    pub fn seek(&mut self, stream: u8, time: i64) -> container::Result<i64> {
+        self.drive_seeking(|demux, input, eof| demux.seek(stream, time, input, eof))
+    }
+
+    /// The packet index, read from the trailer the first time it's asked for.
+    /// `None` for fragmented and legacy files, and for files written without one.
+    pub fn index(&mut self) -> container::Result<Option<&PacketIndex>> {
+        self.drive_seeking(Demuxer::read_index)?;
 
-        let entry = self.seektable[entry];
-        self.reader.seek(std::io::SeekFrom::Start(
-            entry.location as u64 + self.start_of_packets,
-        ))?;
+        Ok(self.demux.index())
+    }
+
+    /// Reads the packet at position `entry` of the index. Reading goes on from there,
+    /// like after a `seek`. Fails with `Error::NoIndex` for files without an index.
+    pub fn read_indexed(&mut self, entry: usize) -> container::Result<(Packet, Vec<u8>)> {
+        self.index()?;
+        self.demux.seek_indexed(entry)?;
+        self.seek_input()?;
 
-        Ok(entry.ts)
+        self.read_packet()
     }
 
-    pub fn read_packet(&mut self) -> std::io::Result<(Packet, Vec<u8>)> {
-        let mut packet = Packet::decode_from(&mut self.reader)?;
+    /// The whole file of attachment `stream`, reading goes on where it was. Attachments are stored
+    /// in front of every other packet, fails with `Error::NoAttachment` if it isn't there.
+    pub fn read_attachment(&mut self, stream: u8) -> container::Result<Vec<u8>> {
+        let checkpoint = self.demux.checkpoint();
 
-        let mut data = vec![0u8; packet.data_len as usize];
-        self.reader.read_exact(&mut data)?;
+        let result = self.find_attachment(stream);
 
-        if let Some(decoder) = self.decoders.get_mut(&packet.stream) {
-            decoder.process(&mut packet, &mut data)?;
-        }
+        self.demux.restore(checkpoint);
+        self.seek_input()?;
 
-        Ok((packet, data))
+        result
     }
 
-    pub fn read_packet_data_into(&mut self, data: &mut Vec<u8>) -> std::io::Result<Packet> {
-        let mut packet = Packet::decode_from(&mut self.reader)?;
+    fn find_attachment(&mut self, stream: u8) -> container::Result<Vec<u8>> {
+        // legacy files predate attachments
+        if self.demux.preamble().is_none() {
+            return Err(Error::NoAttachment { stream });
+        }
 
-        let len = packet.data_len as usize;
-        data.resize(len, 0);
-        self.reader.read_exact(data)?;
+        self.demux.rewind();
+        self.seek_input()?;
 
-        if let Some(decoder) = self.decoders.get_mut(&packet.stream) {
-            decoder.process(&mut packet, data)?;
-        }
+        let mut data = Vec::new();
+        loop {
+            let packet = match self.read_packet_data_into(&mut data) {
+                Err(Error::EndOfStream) => return Err(Error::NoAttachment { stream }),
+                result => result?,
+            };
 
-        Ok(packet)
+            if packet.data_type != PacketDataType::Attachment {
+                return Err(Error::NoAttachment { stream });
+            }
+
+            if packet.stream == stream {
+                return Ok(data);
+            }
+        }
     }
 
-    pub fn read_packet_into_channel(
+    /// `seek`, then decodes forward to the packet of `stream` that covers `time` (microseconds), which is returned.
+    /// Every packet read on the way there (of any stream) is passed to `preroll`, delta frames need them.
+    pub fn seek_exact(
         &mut self,
-        channel: &mpsc::blocking::Sender<PacketWithData, WithCapacity>,
-    ) -> std::io::Result<()> {
-        let mut packet = Packet::decode_from(&mut self.reader)?;
+        stream: u8,
+        time: i64,
+        mut preroll: impl FnMut(&Packet, &[u8]),
+    ) -> container::Result<(Packet, Vec<u8>)> {
+        self.seek(stream, time)?;
+
+        let time_base = self.time_base(stream);
+        let mut data = Vec::new();
+        loop {
+            let packet = self.read_packet_data_into(&mut data)?;
+
+            if packet.stream == stream && ends_after(&packet, time_base, time) {
+                return Ok((packet, data));
+            }
+
+            preroll(&packet, &data);
+        }
+    }
+}
 
-        let mut send_slot = channel.send_ref().unwrap();
+impl<R: Read> Reader<R, states::SeektablesRead> {
+    /// Instead of failing on a damaged packet, skip ahead to the next sync marker and keep a `DamageReport`.
+    pub fn set_recover(&mut self, recover: bool) {
+        self.recover = recover;
+    }
 
-        let len = packet.data_len as usize;
-        send_slot.data.resize(len, 0);
-        self.reader.read_exact(&mut send_slot.data)?;
+    pub fn damage(&self) -> &[DamageReport] {
+        &self.damage
+    }
+
+    /// seek points of `stream`, for fragmented files only those of the first fragment
+    pub fn seektable(&self, stream: u8) -> Option<&[SeekEntry]> {
+        self.demux.seektable(stream)
+    }
+
+    fn try_read_packet(&mut self, data: &mut Vec<u8>) -> container::Result<Packet> {
+        let (offset, mut packet) = self.drive(Demuxer::next_packet)?;
+
+        self.fill(packet.data_len as usize)?;
+        data.clear();
+        data.extend_from_slice(
+            self.demux
+                .payload(offset, &packet, self.input.available())?,
+        );
+        self.move_input()?;
+
+        verify_packet(&self.decoders, offset, &packet, data)?;
+        decompress_packet(
+            &mut self.decoders,
+            self.demux.limits(),
+            offset,
+            &mut packet,
+            data,
+        )?;
 
-        if let Some(decoder) = self.decoders.get_mut(&packet.stream) {
-            decoder.process(&mut packet, &mut send_slot.data)?;
+        Ok(packet)
+    }
+
+    /// Fails with `Error::EndOfStream` once there are no packets left.
+    pub fn read_packet_data_into(&mut self, data: &mut Vec<u8>) -> container::Result<Packet> {
+        loop {
+            let start = self.demux.position();
+
+            match self.try_read_packet(data) {
+                Err(e) if self.recover && e.is_damage() => {
+                    self.drive(Demuxer::resync)?;
+                    self.damage.push(DamageReport {
+                        offset: start,
+                        skipped: self.demux.position() - start,
+                        reason: e.to_string(),
+                    });
+                }
+                result => return result,
+            }
         }
+    }
 
+    pub fn read_packet(&mut self) -> container::Result<(Packet, Vec<u8>)> {
+        let mut data = Vec::new();
+        let packet = self.read_packet_data_into(&mut data)?;
+
+        Ok((packet, data))
+    }
+
+    /// `read_packet`, straight into a slot of `channel`. Fails with a broken pipe once the receiver is gone
+    pub fn read_packet_into_channel(
+        &mut self,
+        channel: &mpsc::blocking::Sender<PacketWithData, WithCapacity>,
+    ) -> container::Result<()> {
+        // read first, so nothing gets sent when the file ends
+        let mut data = std::mem::take(&mut self.scratch);
+        let packet = self.read_packet_data_into(&mut data);
+        self.scratch = data;
+
+        let packet = packet?;
+
+        let mut send_slot = channel
+            .send_ref()
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "packet receiver hung up"))?;
+        std::mem::swap(&mut send_slot.data, &mut self.scratch);
         send_slot.header = packet;
 
         Ok(())
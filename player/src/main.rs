@@ -1,32 +1,77 @@
 use clap::Parser;
+use container::encryption;
 use crossterm::{
     event::{
         DisableBracketedPaste, DisableFocusChange, DisableMouseCapture, EnableBracketedPaste,
         EnableFocusChange, EnableMouseCapture, Event, KeyCode, KeyboardEnhancementFlags,
         MouseButton, MouseEventKind, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
-        read,
+        poll, read,
     },
     execute, queue,
     terminal::{Clear, disable_raw_mode, enable_raw_mode},
 };
-use player::renderer::PlayerControl;
+use player::{Reader, processors::KeyRing, renderer::PlayerControl};
 use std::{
     fs::File,
-    io::{self, BufReader, BufWriter, Write},
-    path::PathBuf,
+    io::{self, BufReader, Read, Seek, SeekFrom, Stdin, Write},
+    path::{Path, PathBuf},
     time::Duration,
 };
 
 #[derive(clap::Parser)]
 struct PlayArgs {
+    /// File to play, or "-" to play a fragmented stream from stdin
     file: PathBuf,
     #[arg(long)]
     subtitle_index: Option<u8>,
+    /// Subtitle languages to pick from, most preferred first, as they're named in the file (e.g. "eng,jpn")
+    #[arg(long, value_delimiter = ',')]
+    subtitle_languages: Vec<String>,
+    /// Key file (64 hex digits) for encrypted files, as ID=FILE if it was encoded with a key id.
+    /// Can be given more than once
+    #[arg(long, value_name = "[ID=]FILE")]
+    key: Vec<String>,
+}
+
+// stdin can't seek, `PlayerControl::seekable` keeps seeks away from it
+enum Input {
+    File(BufReader<File>),
+    Stdin(BufReader<Stdin>),
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Input::File(file) => file.read(buf),
+            Input::Stdin(stdin) => stdin.read(buf),
+        }
+    }
+}
+
+impl Seek for Input {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Input::File(file) => file.seek(pos),
+            Input::Stdin(_) => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
 }
 
 fn main() -> anyhow::Result<()> {
     let cli = PlayArgs::parse();
 
+    if !cfg!(feature = "encryption") && !cli.key.is_empty() {
+        anyhow::bail!("--key: this player was built without the `encryption` feature");
+    }
+
+    let mut keys = KeyRing::default();
+    for spec in &cli.key {
+        let (id, path) = spec.split_once('=').unwrap_or(("", spec));
+        let key = encryption::parse_key(&std::fs::read_to_string(path)?)
+            .ok_or_else(|| anyhow::anyhow!("{path}: expected a key of 64 hex digits"))?;
+        keys.insert(id, key);
+    }
+
     enable_raw_mode()?;
 
     let mut stdout = io::stdout();
@@ -57,9 +102,19 @@ fn main() -> anyhow::Result<()> {
 
     stdout.flush()?;
 
-    let stdout = BufWriter::with_capacity(192 * 108 * 20, stdout);
+    let streaming = cli.file == Path::new("-");
+    let mut input = Reader::new(if streaming {
+        Input::Stdin(BufReader::new(io::stdin()))
+    } else {
+        Input::File(BufReader::new(File::open(cli.file)?))
+    });
+    input.set_keys(keys);
 
-    let mut renderer = PlayerControl::new(BufReader::new(File::open(cli.file)?), stdout)?;
+    let mut renderer = if streaming {
+        PlayerControl::from_reader_streaming(input, stdout)?
+    } else {
+        PlayerControl::from_reader(input, stdout)?
+    };
     let video_track = renderer.video_stream.clone();
     let video_params = video_track.parameters.as_video().unwrap().clone();
 
@@ -68,7 +123,20 @@ fn main() -> anyhow::Result<()> {
     } else {
         // let mut subtitle_options: Vec<&Stream> = renderer.header.tracks.iter().filter(|s| s.parameters.is_subtitle()).collect();
         // writeln!(io::stdout(), "select subti")
-        renderer.auto_select_subtitles();
+        renderer.auto_select_subtitles(&cli.subtitle_languages);
+    }
+
+    // the cover stays up for a few seconds, or until a key is pressed
+    if let Some(cover) = renderer.cover.as_ref() {
+        let mut stdout = io::stdout();
+        stdout.write_all(b"\x1b[1;1H")?;
+        stdout.write_all(cover)?;
+        stdout.write_all(b"\x1b[0m")?;
+        stdout.flush()?;
+
+        if poll(Duration::from_secs(3))? {
+            read()?;
+        }
     }
 
     renderer.resume();
@@ -82,6 +150,9 @@ fn main() -> anyhow::Result<()> {
                 match k.code {
                     KeyCode::Char('a') => renderer.seek_backwards(Duration::from_secs(5))?,
                     KeyCode::Char('d') => renderer.seek_forward(Duration::from_secs(5))?,
+                    KeyCode::Char('n') if renderer.seekable => renderer.next_chapter()?,
+                    KeyCode::Char('b') if renderer.seekable => renderer.previous_chapter()?,
+                    KeyCode::Char('i') => renderer.toggle_info(),
                     KeyCode::Char('r') => renderer.resume(),
                     KeyCode::Char('p') => renderer.pause(),
                     KeyCode::Char('q') => {
@@ -100,7 +171,10 @@ fn main() -> anyhow::Result<()> {
                 };
             }
             Event::Mouse(m) if m.kind == MouseEventKind::Down(MouseButton::Left) => {
-                if m.row == (video_params.height / 2) && m.column < video_params.width {
+                if renderer.seekable
+                    && m.row == (video_params.height / 2)
+                    && m.column < video_params.width
+                {
                     let pct = m.column as f64 / video_params.width as f64;
                     let time = pct * video_track.duration as f64;
                     renderer.seek(Duration::from_micros(time.round() as u64))?;
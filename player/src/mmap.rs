@@ -0,0 +1,176 @@
+use std::{fs::File, io, ops::Range, path::Path};
+
+use container::{
+    Packet,
+    demux::{Demuxer, Step},
+    limits::DecodeLimits,
+    metadata::FormatData,
+    preamble::Preamble,
+    seek::SeekEntry,
+};
+use memmap2::Mmap;
+
+use crate::{Decoders, create_decoders, decompress_packet, processors::KeyRing, verify_packet};
+
+/// A packet borrowed from an `MmapReader`. `data` points right into the file for uncompressed streams,
+/// compressed ones get decompressed into a buffer the reader reuses.
+pub struct PacketRef<'a> {
+    /// where the packet header starts, see `MmapReader::packet_at`
+    pub offset: u64,
+    pub header: Packet,
+    pub data: &'a [u8],
+}
+
+/// Reads a file through a memory map, without copying the packets of uncompressed streams.
+/// Damaged files are not recovered, use `Reader::set_recover` for those.
+pub struct MmapReader {
+    map: Mmap,
+    demux: Demuxer,
+    decoders: Decoders,
+    header: FormatData,
+    buffer: Vec<u8>,
+}
+
+enum Payload {
+    Mapped(Range<usize>),
+    Buffer,
+}
+
+impl MmapReader {
+    pub fn open(path: impl AsRef<Path>) -> container::Result<MmapReader> {
+        let file = File::open(path)?;
+
+        // SAFETY: nothing else may truncate or write to the file while it's mapped.
+        // that's the deal with every memory map, readers of files still being encoded should use `Reader`
+        let map = unsafe { Mmap::map(&file)? };
+
+        MmapReader::new(map)
+    }
+
+    pub fn new(map: Mmap) -> container::Result<MmapReader> {
+        MmapReader::with_limits(map, DecodeLimits::default())
+    }
+
+    pub fn with_limits(map: Mmap, limits: DecodeLimits) -> container::Result<MmapReader> {
+        let mut demux = Demuxer::new();
+        demux.set_limits(limits);
+
+        let (header, offset) = drive(&map, &mut demux, Demuxer::read_header)?;
+        drive(&map, &mut demux, Demuxer::read_seektables)?;
+        let decoders = create_decoders(&header, &KeyRing::default(), offset)?;
+
+        Ok(MmapReader {
+            map,
+            demux,
+            decoders,
+            header,
+            buffer: Vec::new(),
+        })
+    }
+
+    pub fn header(&self) -> &FormatData {
+        &self.header
+    }
+
+    /// format version and layout, `None` for legacy (v3.0) files
+    pub fn preamble(&self) -> Option<&Preamble> {
+        self.demux.preamble()
+    }
+
+    /// seek points of `stream`, for fragmented files only those of the first fragment
+    pub fn seektable(&self, stream: u8) -> Option<&[SeekEntry]> {
+        self.demux.seektable(stream)
+    }
+
+    /// see `Reader::seek`
+    pub fn seek(&mut self, stream: u8, time: i64) -> container::Result<i64> {
+        drive(&self.map, &mut self.demux, |demux, input, eof| {
+            demux.seek(stream, time, input, eof)
+        })
+    }
+
+    /// Fails with `Error::EndOfStream` once there are no packets left.
+    pub fn next_packet(&mut self) -> container::Result<PacketRef<'_>> {
+        let (offset, header, payload) = self.read_next()?;
+        Ok(self.packet_ref(offset, header, payload))
+    }
+
+    /// Reads the packet whose header starts at `offset`, without moving `next_packet` along.
+    /// Varint headers (format 5.0 and up) may store their timestamp relative to a packet before them,
+    /// those can only be read right where `next_packet` would continue. Seek points, keyframes
+    /// and the first packet of every fragment always have an absolute one.
+    pub fn packet_at(&mut self, offset: u64) -> container::Result<PacketRef<'_>> {
+        let checkpoint = self.demux.checkpoint();
+
+        if offset != self.demux.position() {
+            self.demux.seek_to_packet(offset);
+        }
+
+        let result = self.read_next();
+        self.demux.restore(checkpoint);
+
+        let (offset, header, payload) = result?;
+        Ok(self.packet_ref(offset, header, payload))
+    }
+
+    fn read_next(&mut self) -> container::Result<(u64, Packet, Payload)> {
+        let (offset, mut packet) = drive(&self.map, &mut self.demux, Demuxer::next_packet)?;
+
+        let start = self.demux.position() as usize;
+        let input = self.map.get(start..).unwrap_or_default();
+        let data = self.demux.payload(offset, &packet, input)?;
+        let range = start..start + data.len();
+
+        verify_packet(&self.decoders, offset, &packet, data)?;
+
+        if !self.decoders.contains_key(&packet.stream) {
+            return Ok((offset, packet, Payload::Mapped(range)));
+        }
+
+        self.buffer.clear();
+        self.buffer.extend_from_slice(data);
+        decompress_packet(
+            &mut self.decoders,
+            self.demux.limits(),
+            offset,
+            &mut packet,
+            &mut self.buffer,
+        )?;
+
+        Ok((offset, packet, Payload::Buffer))
+    }
+
+    fn packet_ref(&self, offset: u64, header: Packet, payload: Payload) -> PacketRef<'_> {
+        let data = match payload {
+            Payload::Mapped(range) => &self.map[range],
+            Payload::Buffer => self.buffer.as_slice(),
+        };
+
+        PacketRef {
+            offset,
+            header,
+            data,
+        }
+    }
+}
+
+// runs `step` on the map until it's done, all of the file is always there
+fn drive<T>(
+    map: &Mmap,
+    demux: &mut Demuxer,
+    mut step: impl FnMut(&mut Demuxer, &[u8], bool) -> container::Result<Step<T>>,
+) -> container::Result<T> {
+    loop {
+        let input = usize::try_from(demux.position())
+            .ok()
+            .and_then(|position| map.get(position..))
+            .unwrap_or_default();
+
+        match step(demux, input, true)? {
+            Step::Done(value) => return Ok(value),
+            Step::Moved => {}
+            // never asked for with `eof` set
+            Step::Needs(_) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        }
+    }
+}
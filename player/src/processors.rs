@@ -1,11 +1,41 @@
 use std::io;
 
-use container::{Packet, side_data};
+#[cfg(feature = "encryption")]
+use chacha20poly1305::{
+    XChaCha20Poly1305, XNonce,
+    aead::{AeadInPlace, KeyInit},
+};
+#[cfg(feature = "encryption")]
+use container::encryption;
+use container::{Error, Packet, encryption::KEY_LEN, metadata::CompressionMode, side_data};
+use litemap::LiteMap;
 use lz4_flex::{block::decompress_into_with_dict, decompress_into};
 use zstd::bulk::Decompressor;
 
 pub trait DecoderProcessor {
-    fn process(&mut self, packet: &mut Packet, data: &mut Vec<u8>) -> io::Result<()>;
+    fn process(&mut self, packet: &mut Packet, data: &mut Vec<u8>) -> container::Result<()>;
+}
+
+// packets of compressed streams carry the method and the length to decompress to
+fn decompressed_len(packet: &Packet, mode: CompressionMode) -> container::Result<u64> {
+    if let Some(method) = packet.side_data.get(&side_data::COMPRESSION_METHOD)
+        && method.as_slice() != [mode as u8]
+    {
+        return Err(Error::UnknownCompression {
+            offset: None,
+            stream: packet.stream,
+            method: method.first().copied().unwrap_or_default(),
+        });
+    }
+
+    packet
+        .side_data
+        .get_u64(&side_data::DECOMPRESSED_LEN)
+        .ok_or_else(|| Error::Decompression {
+            offset: None,
+            stream: packet.stream,
+            reason: "side data: decompressed len is missing".to_string(),
+        })
 }
 
 #[derive(Default)]
@@ -24,16 +54,8 @@ impl Lz4Decoder {
 }
 
 impl DecoderProcessor for Lz4Decoder {
-    fn process(&mut self, packet: &mut Packet, data: &mut Vec<u8>) -> io::Result<()> {
-        let decompressed_len = packet
-            .side_data
-            .get(&side_data::DECOMPRESSED_LEN)
-            .and_then(|v| v.as_slice().try_into().ok())
-            .map(u64::from_le_bytes)
-            .ok_or(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "side data: decompressed len is missing",
-            ))?;
+    fn process(&mut self, packet: &mut Packet, data: &mut Vec<u8>) -> container::Result<()> {
+        let decompressed_len = decompressed_len(packet, CompressionMode::Lz4)?;
 
         self.scratch.clear();
         self.scratch.resize(decompressed_len as usize, 0);
@@ -42,7 +64,11 @@ impl DecoderProcessor for Lz4Decoder {
             Some(dict) => decompress_into_with_dict(data, &mut self.scratch, dict),
             None => decompress_into(data, &mut self.scratch),
         }
-        .map_err(io::Error::other)?;
+        .map_err(|e| Error::Decompression {
+            offset: None,
+            stream: packet.stream,
+            reason: e.to_string(),
+        })?;
 
         data.clear();
         data.append(&mut self.scratch);
@@ -69,22 +95,19 @@ impl ZstdDecoder {
 }
 
 impl DecoderProcessor for ZstdDecoder {
-    fn process(&mut self, packet: &mut Packet, data: &mut Vec<u8>) -> io::Result<()> {
-        let decompressed_len = packet
-            .side_data
-            .get(&side_data::DECOMPRESSED_LEN)
-            .and_then(|v| v.as_slice().try_into().ok())
-            .map(u64::from_le_bytes)
-            .ok_or(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "side data: decompressed len is missing",
-            ))?;
+    fn process(&mut self, packet: &mut Packet, data: &mut Vec<u8>) -> container::Result<()> {
+        let decompressed_len = decompressed_len(packet, CompressionMode::Zstd)?;
 
         self.scratch.clear();
         self.scratch.reserve(decompressed_len as usize);
 
         self.decompressor
-            .decompress_to_buffer(data, &mut self.scratch)?;
+            .decompress_to_buffer(data, &mut self.scratch)
+            .map_err(|e| Error::Decompression {
+                offset: None,
+                stream: packet.stream,
+                reason: e.to_string(),
+            })?;
 
         data.clear();
         data.append(&mut self.scratch);
@@ -92,3 +115,106 @@ impl DecoderProcessor for ZstdDecoder {
         Ok(())
     }
 }
+
+/// Keys for encrypted files (see `container::encryption`), by the key id packets name them with.
+/// Give it to `Reader::set_keys` before reading the header.
+#[derive(Default, Clone)]
+pub struct KeyRing {
+    keys: LiteMap<Vec<u8>, [u8; KEY_LEN]>,
+}
+
+impl KeyRing {
+    /// `id` is the one the file was encoded with, empty if it wasn't given one
+    pub fn insert(&mut self, id: impl Into<Vec<u8>>, key: [u8; KEY_LEN]) {
+        self.keys.insert(id.into(), key);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+/// Opens encrypted payloads, then hands them on to the stream's decompressor (if it has one).
+#[cfg(feature = "encryption")]
+pub struct Decryptor {
+    ciphers: LiteMap<Vec<u8>, XChaCha20Poly1305>,
+    next: Option<Box<dyn DecoderProcessor + Send>>,
+}
+
+#[cfg(feature = "encryption")]
+impl Decryptor {
+    pub fn new(keys: &KeyRing, next: Option<Box<dyn DecoderProcessor + Send>>) -> Self {
+        Decryptor {
+            ciphers: keys
+                .keys
+                .iter()
+                .map(|(id, key)| (id.clone(), XChaCha20Poly1305::new(key.into())))
+                .collect(),
+            next,
+        }
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl DecoderProcessor for Decryptor {
+    fn process(&mut self, packet: &mut Packet, data: &mut Vec<u8>) -> container::Result<()> {
+        let error = |reason: &str| Error::Decryption {
+            offset: None,
+            stream: packet.stream,
+            reason: reason.to_string(),
+        };
+
+        // encrypted files have no plaintext payloads, one without a nonce has been tampered with
+        let nonce = packet
+            .side_data
+            .get(&side_data::NONCE)
+            .filter(|nonce| nonce.len() == encryption::NONCE_LEN)
+            .ok_or_else(|| error("side data: nonce is missing"))?;
+
+        let key_id = packet
+            .side_data
+            .get(&side_data::KEY_ID)
+            .map_or(&[][..], Vec::as_slice);
+        let cipher = self
+            .ciphers
+            .get(key_id)
+            .ok_or_else(|| error(&format!("no key with id \"{}\"", key_id.escape_ascii())))?;
+
+        cipher
+            .decrypt_in_place(
+                XNonce::from_slice(nonce),
+                &encryption::associated_data(packet),
+                data,
+            )
+            .map_err(|_| error("payload doesn't authenticate (wrong key?)"))?;
+
+        packet.data_len = data.len() as u64;
+
+        match self.next.as_mut() {
+            Some(next) => next.process(packet, data),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Fails every packet, this build can't decrypt.
+#[cfg(not(feature = "encryption"))]
+pub struct Decryptor;
+
+#[cfg(not(feature = "encryption"))]
+impl Decryptor {
+    pub fn new(_keys: &KeyRing, _next: Option<Box<dyn DecoderProcessor + Send>>) -> Self {
+        Decryptor
+    }
+}
+
+#[cfg(not(feature = "encryption"))]
+impl DecoderProcessor for Decryptor {
+    fn process(&mut self, packet: &mut Packet, _data: &mut Vec<u8>) -> container::Result<()> {
+        Err(Error::Decryption {
+            offset: None,
+            stream: packet.stream,
+            reason: "built without the `encryption` feature".to_string(),
+        })
+    }
+}
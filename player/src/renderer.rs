@@ -1,35 +1,61 @@
 use container::{
-    EncodableData, PacketDataType, SubRect, SubRectVec,
-    metadata::{FormatData, Stream},
+    Error, PacketDataType, SubRectVec,
+    cells::CellFrame,
+    limits::DecodeLimits,
+    metadata::{
+        AudioCodec, FormatData, Stream, TimeBase, VideoCodec, VideoParameters, disposition, tags,
+    },
 };
 use crossterm::{
     execute,
     terminal::{BeginSynchronizedUpdate, EndSynchronizedUpdate},
 };
+use litemap::LiteMap;
 use parking_lot::{Condvar, Mutex};
 use spin_sleep::SpinSleeper;
 use stable_vec::StableVec;
 use std::{
-    io::{self, IoSlice, Read, Seek, Write},
-    sync::{Arc, atomic::AtomicU8},
+    io::{self, BufWriter, IoSlice, Read, Seek, Write},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU8, AtomicU64},
+    },
     thread::JoinHandle,
     time::{Duration, Instant},
 };
 use thingbuf::{mpsc::blocking::Receiver, recycling::WithCapacity};
 
-use crate::{FormatDuration, PacketWithData, Reader, states};
+use crate::{
+    FormatDuration, PacketWithData, Reader,
+    audio::{AudioOutput, AudioSink},
+    packet_capacity, states,
+};
+
+// packets read ahead of the render thread
+const PACKET_SLOTS: usize = 100;
+// the read ahead allocates all of its slots at once, stream stats claiming more than this don't get trusted
+const MAX_SLOT_CAPACITY: usize = 4 << 20;
 
-pub struct PlayerControl<R: Read + Seek + Send + 'static> {
+pub struct PlayerControl<R: Read + Send + 'static> {
     pub state: RendererState,
     pause_time: Option<Instant>,
+    /// false when playing from a pipe
+    pub seekable: bool,
 
     pub header: FormatData,
     pub video_stream: Stream,
+    pub audio_stream: Option<Stream>,
+    /// why `audio_stream` isn't played (no output device, built without audio support), also shown in the info overlay
+    pub audio_error: Option<anyhow::Error>,
+    /// cover art as escape sequences, to show before playback starts. Only read from seekable input
+    pub cover: Option<Vec<u8>>,
 
     reader_handle: Arc<Mutex<Reader<R, states::SeektablesRead>>>,
+    reader_error: Arc<Mutex<Option<Error>>>,
 
     reader_thread: JoinHandle<()>,
     render_thread: JoinHandle<()>,
+    _audio_output: Option<AudioOutput>,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
@@ -41,11 +67,36 @@ pub enum PlayThreadState {
     DiscardDone,
 }
 
+/// An event of a data stream (see `container::metadata::DataParameters`), passed to the callback set
+/// with `PlayerControl::on_data` once playback gets to it.
+pub struct DataEvent<'a> {
+    pub stream: u8,
+    pub start: Duration,
+    pub end: Duration,
+    pub data: &'a [u8],
+}
+
+impl DataEvent<'_> {
+    /// the payload of `DataEncoding::Utf8` streams
+    pub fn text(&self) -> Option<&str> {
+        std::str::from_utf8(self.data).ok()
+    }
+}
+
+pub type DataCallback = Box<dyn FnMut(&DataEvent<'_>) + Send>;
+
 pub struct RendererState {
     pub play_status: Arc<(Mutex<PlayThreadState>, Condvar)>,
     pub current_time: Arc<Mutex<Instant>>,
     pub video_time: Arc<Mutex<Duration>>,
     pub subtitle_index: Arc<AtomicU8>,
+    /// draw the metadata overlay on top of the video
+    pub show_info: Arc<AtomicBool>,
+    /// microseconds: after a seek, packets before this are decoded but not shown
+    pub preroll_until: Arc<AtomicU64>,
+    pub on_data: Arc<Mutex<Option<DataCallback>>>,
+    /// those of the input, for the packets the render thread decodes (cell grids)
+    pub limits: DecodeLimits,
 }
 
 impl Clone for RendererState {
@@ -55,33 +106,183 @@ impl Clone for RendererState {
             current_time: Arc::clone(&self.current_time),
             video_time: Arc::clone(&self.video_time),
             subtitle_index: Arc::clone(&self.subtitle_index),
+            show_info: Arc::clone(&self.show_info),
+            preroll_until: Arc::clone(&self.preroll_until),
+            on_data: Arc::clone(&self.on_data),
+            limits: self.limits,
         }
     }
 }
 
 impl<R: Read + Seek + Send + 'static> PlayerControl<R> {
     pub fn new(input: R, output: impl Write + Send + 'static) -> anyhow::Result<PlayerControl<R>> {
-        let input = Reader::new(input);
+        PlayerControl::from_reader(Reader::new(input), output)
+    }
+
+    /// for a reader set up beforehand, e.g. with `Reader::set_keys`
+    pub fn from_reader(
+        input: Reader<R, states::Start>,
+        output: impl Write + Send + 'static,
+    ) -> anyhow::Result<PlayerControl<R>> {
         let (input, header) = input.read_header()?;
-        let (input, seektables) = input.read_seektables()?;
+        let (mut input, _) = input.read_seektables()?;
+
+        // a cover that can't be read isn't worth failing over
+        let cover = header
+            .ansi_still()
+            .and_then(|stream| input.read_attachment(stream.index).ok());
+
+        PlayerControl::start(input, header, true, cover, output)
+    }
+
+    pub fn seek(&mut self, time: Duration) -> io::Result<()> {
+        if !self.seekable {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "input can't be seeked",
+            ));
+        }
+
+        let wait_start = Instant::now();
+        let mut reader = self.reader_handle.lock();
+
+        // land on the exact frame: start decoding at the keyframe before it, the renderer skips ahead.
+        // seeking goes first, the render thread must not be left waiting on a discard if it fails
+        let target = time.as_micros() as i64;
+        let keyframe_time = reader.seek(self.video_stream.index, target)?;
+        let actual_time = target.max(keyframe_time);
+
+        let old_state = *self.state.play_status.0.lock();
+
+        *self.state.play_status.0.lock() = PlayThreadState::DiscardRequest;
+        self.state.play_status.1.notify_all();
+
+        let mut current_time = self.state.current_time.lock();
+        let video_time = self.state.video_time.lock();
+
+        // if seek forward: reduce current_time by difference, else add
+        self.state
+            .preroll_until
+            .store(actual_time as u64, std::sync::atomic::Ordering::Release);
+
+        let delta = video_time.as_micros() as i64 - actual_time;
+        if delta >= 0 {
+            *current_time += Duration::from_micros(delta as u64);
+        } else {
+            *current_time -= Duration::from_micros(delta.unsigned_abs());
+        }
+
+        self.wait_for_state(|v| *v != PlayThreadState::DiscardDone);
+
+        drop(reader);
+
+        *current_time += wait_start.elapsed();
+
+        drop(current_time);
+        drop(video_time);
+
+        *self.state.play_status.0.lock() = old_state;
+        self.state.play_status.1.notify_all();
+
+        Ok(())
+    }
+
+    pub fn seek_forward(&mut self, time: Duration) -> io::Result<()> {
+        let target_time = *self.state.video_time.lock() + time;
+        self.seek(target_time)
+    }
+
+    pub fn seek_backwards(&mut self, time: Duration) -> io::Result<()> {
+        let target_time = (*self.state.video_time.lock())
+            .checked_sub(time)
+            .unwrap_or_default();
+        self.seek(target_time)
+    }
+
+    pub fn next_chapter(&mut self) -> io::Result<()> {
+        let now = *self.state.video_time.lock();
+        let next = self
+            .header
+            .chapters()
+            .iter()
+            .map(|chapter| Duration::from_micros(chapter.start))
+            .find(|&start| start > now);
+
+        match next {
+            Some(start) => self.seek(start),
+            None => Ok(()),
+        }
+    }
+
+    /// back to the start of the current chapter, or to the one before if that was just now
+    pub fn previous_chapter(&mut self) -> io::Result<()> {
+        let now = *self.state.video_time.lock();
+        let previous = self
+            .header
+            .chapters()
+            .iter()
+            .map(|chapter| Duration::from_micros(chapter.start))
+            .rfind(|&start| start + Duration::from_secs(3) <= now);
+
+        self.seek(previous.unwrap_or_default())
+    }
+}
+
+impl<R: Read + Send + 'static> PlayerControl<R> {
+    /// for input that can't seek (stdin, pipes): needs a fragmented (or legacy) file, and seeking stays unavailable
+    pub fn new_streaming(
+        input: R,
+        output: impl Write + Send + 'static,
+    ) -> anyhow::Result<PlayerControl<R>> {
+        PlayerControl::from_reader_streaming(Reader::new(input), output)
+    }
+
+    /// `from_reader` for input that can't seek
+    pub fn from_reader_streaming(
+        input: Reader<R, states::Start>,
+        output: impl Write + Send + 'static,
+    ) -> anyhow::Result<PlayerControl<R>> {
+        let (input, header) = input.read_header_streaming()?;
+        let (input, _) = input.read_seektables_streaming()?;
+
+        PlayerControl::start(input, header, false, None, output)
+    }
+
+    fn start(
+        mut input: Reader<R, states::SeektablesRead>,
+        header: FormatData,
+        seekable: bool,
+        cover: Option<Vec<u8>>,
+        output: impl Write + Send + 'static,
+    ) -> anyhow::Result<PlayerControl<R>> {
+        // a glitch in the picture beats stopping playback
+        input.set_recover(true);
+
+        // every slot of the channel gets this much up front, the odd bigger packet grows its slot
+        let slot_capacity = packet_capacity(&header, input.limits()).min(MAX_SLOT_CAPACITY);
+        let limits = *input.limits();
 
         let input = Arc::new(Mutex::new(input));
 
         let (packet_tx, packet_rx) = thingbuf::mpsc::blocking::with_recycle::<PacketWithData, _>(
-            100,
-            WithCapacity::new().with_min_capacity(192 * 108 * 20),
+            PACKET_SLOTS,
+            WithCapacity::new().with_min_capacity(slot_capacity),
         );
 
         let input_handle = Arc::clone(&input);
+        let reader_error = Arc::new(Mutex::new(None));
+        let error_handle = Arc::clone(&reader_error);
         let reader_thread = std::thread::spawn(move || {
-            while let Ok(mut slot) = packet_tx.send_ref() {
-                let mut reader_lock = input_handle.lock();
-                let packet = reader_lock.read_packet_data_into(&mut slot.data);
-                drop(reader_lock);
-                slot.header = packet.unwrap();
-            }
+            // playback ends with whatever made the reader stop, after the packets read up to there
+            let result = loop {
+                if let Err(e) = input_handle.lock().read_packet_into_channel(&packet_tx) {
+                    break e;
+                }
+            };
 
-            while let Ok(()) = input_handle.lock().read_packet_into_channel(&packet_tx) {}
+            if !matches!(result, Error::EndOfStream) {
+                *error_handle.lock() = Some(result);
+            }
 
             drop(packet_tx);
         });
@@ -91,6 +292,10 @@ impl<R: Read + Seek + Send + 'static> PlayerControl<R> {
             current_time: Arc::new(Mutex::new(Instant::now())),
             video_time: Default::default(),
             subtitle_index: Arc::new(AtomicU8::new(255)),
+            show_info: Arc::new(AtomicBool::new(false)),
+            preroll_until: Arc::new(AtomicU64::new(0)),
+            on_data: Default::default(),
+            limits,
         };
 
         let pause_time = Some(Instant::now());
@@ -103,89 +308,126 @@ impl<R: Read + Seek + Send + 'static> PlayerControl<R> {
             .unwrap()
             .clone();
         // let total_duration = Duration::from_micros(video_stream.duration);
+
+        let audio_stream = header
+            .tracks
+            .iter()
+            .find(|v: &&Stream| {
+                v.parameters
+                    .as_audio()
+                    .is_some_and(|params| params.codec == AudioCodec::Pcm)
+            })
+            .cloned();
+
+        // no output device (or no audio support) just means silent playback
+        let mut audio_error = None;
+        let (audio_sink, audio_output) = audio_stream
+            .as_ref()
+            .map(|stream| AudioSink::new(stream.index, stream.parameters.as_audio().unwrap()))
+            .and_then(|sink| match AudioOutput::new(sink.clone(), state.clone()) {
+                Ok(output) => Some((sink, output)),
+                Err(e) => {
+                    audio_error = Some(e);
+                    None
+                }
+            })
+            .unzip();
+
+        // a whole frame goes out in one write
+        let frame_capacity = video_stream
+            .stats
+            .map_or(slot_capacity, |stats| stats.max_decompressed_size as usize)
+            .min(MAX_SLOT_CAPACITY);
+        let output = BufWriter::with_capacity(frame_capacity, output);
+
         let video_two = video_stream.clone();
-        let render_thread =
-            std::thread::spawn(move || render_loop(video_two, output, packet_rx, state_handle));
+        let header_two = header.clone();
+        let info = info_overlay(
+            &header,
+            video_stream.parameters.as_video().unwrap(),
+            audio_error.as_ref(),
+        );
+        let render_thread = std::thread::spawn(move || {
+            render_loop(
+                video_two,
+                header_two,
+                info,
+                output,
+                packet_rx,
+                state_handle,
+                audio_sink,
+            )
+        });
 
         Ok(PlayerControl {
             state,
             pause_time,
+            seekable,
             video_stream,
+            audio_stream,
+            audio_error,
+            cover,
             header,
             reader_handle: input,
+            reader_error,
             reader_thread,
             render_thread,
+            _audio_output: audio_output,
         })
     }
 
-    pub fn auto_select_subtitles(&self) {
-        for stream in &self.header.tracks {
-            if stream.parameters.is_subtitle() {
-                self.state
-                    .subtitle_index
-                    .store(stream.index, std::sync::atomic::Ordering::Release);
-                break;
-            }
+    /// Picks the subtitle stream in the first of `languages` there is one in (compared ignoring case,
+    /// so they have to be written like in the file, usually ISO 639-2 like "eng"), any language if none match.
+    /// Among those, full dialogue tracks win over forced (signs and songs) and commentary ones,
+    /// plain ones over ones for the hearing impaired, then the one marked default, then the first.
+    pub fn auto_select_subtitles(&self, languages: &[impl AsRef<str>]) {
+        let best = self
+            .header
+            .tracks
+            .iter()
+            .filter(|stream| stream.parameters.is_subtitle())
+            .min_by_key(|stream| {
+                let language_rank = stream
+                    .language()
+                    .and_then(|lang| {
+                        languages
+                            .iter()
+                            .position(|preferred| preferred.as_ref().eq_ignore_ascii_case(lang))
+                    })
+                    .unwrap_or(languages.len());
+
+                (
+                    language_rank,
+                    stream.has_disposition(disposition::COMMENTARY),
+                    stream.has_disposition(disposition::FORCED),
+                    stream.has_disposition(disposition::HEARING_IMPAIRED),
+                    !stream.has_disposition(disposition::DEFAULT),
+                )
+            });
+
+        if let Some(stream) = best {
+            self.state
+                .subtitle_index
+                .store(stream.index, std::sync::atomic::Ordering::Release);
         }
     }
 
-    pub fn select_subtitles(&self, index: u8) {
+    pub fn toggle_info(&self) {
         self.state
-            .subtitle_index
-            .store(index, std::sync::atomic::Ordering::Release);
-    }
-
-    pub fn seek(&mut self, time: Duration) -> io::Result<()> {
-        let wait_start = Instant::now();
-        let mut reader = self.reader_handle.lock();
-
-        let old_state = *self.state.play_status.0.lock();
-
-        *self.state.play_status.0.lock() = PlayThreadState::DiscardRequest;
-        self.state.play_status.1.notify_all();
-
-        let mut current_time = self.state.current_time.lock();
-        let video_time = self.state.video_time.lock();
-
-        // if seek forward: reduce current_time by difference, else add
-        let actual_time = reader.seek(time.as_micros() as i64)?;
-
-        let delta = video_time.as_micros() as i64 - actual_time;
-        if delta >= 0 {
-            *current_time += Duration::from_micros(delta as u64);
-        } else {
-            *current_time -= Duration::from_micros(delta.unsigned_abs());
-        }
-
-        self.wait_for_state(|v| *v != PlayThreadState::DiscardDone);
-
-        drop(reader);
-
-        *current_time += wait_start.elapsed();
-
-        drop(current_time);
-        drop(video_time);
-
-        *self.state.play_status.0.lock() = old_state;
-        self.state.play_status.1.notify_all();
-
-        Ok(())
+            .show_info
+            .fetch_xor(true, std::sync::atomic::Ordering::AcqRel);
     }
 
-    pub fn seek_forward(&mut self, time: Duration) -> io::Result<()> {
-        let target_time = *self.state.video_time.lock() + time;
-        self.seek(target_time);
-
-        Ok(())
+    /// Called on the render thread for every event of a data stream, when playback gets to its start.
+    /// Events skipped over by a seek aren't passed on. Anything slow in there holds up the video.
+    pub fn on_data(&self, callback: impl FnMut(&DataEvent<'_>) + Send + 'static) {
+        *self.state.on_data.lock() = Some(Box::new(callback));
     }
 
-    pub fn seek_backwards(&mut self, time: Duration) -> io::Result<()> {
-        let target_time = (*self.state.video_time.lock())
-            .checked_sub(time)
-            .unwrap_or_default();
-        self.seek(target_time);
-
-        Ok(())
+    pub fn select_subtitles(&self, index: u8) {
+        self.state
+            .subtitle_index
+            .store(index, std::sync::atomic::Ordering::Release);
     }
 
     fn wait_for_state(&self, mut keep_waiting: impl FnMut(&mut PlayThreadState) -> bool) {
@@ -222,6 +464,12 @@ impl<R: Read + Seek + Send + 'static> PlayerControl<R> {
         self.state.play_status.1.notify_all();
     }
 
+    /// Why reading stopped before the end of the file (cut off, unreadable, the render thread is gone), once.
+    /// Damage is skipped over and doesn't end up here.
+    pub fn reader_error(&self) -> Option<Error> {
+        self.reader_error.lock().take()
+    }
+
     pub fn join(self) {
         self.reader_thread.join();
         self.render_thread.join();
@@ -243,9 +491,12 @@ struct Subtitle {
 
 fn render_loop(
     video_stream: Stream,
+    header: FormatData,
+    info: String,
     mut output: impl Write + Send + 'static,
     receiver: Receiver<PacketWithData, WithCapacity>,
     state: RendererState,
+    audio: Option<AudioSink>,
 ) {
     output.write_all(b"\x1b[1;1H\x1b[?25l").unwrap();
     output.flush().unwrap();
@@ -256,6 +507,42 @@ fn render_loop(
     let video_params = video_stream.parameters.as_video().unwrap().clone();
     let total_duration = Duration::from_micros(video_stream.duration);
 
+    // no subtitle is stored more than this ahead of the frames around it (see `FormatData::max_interleave`),
+    // ones further ahead are left over from before seeking back and get read again
+    let subs_ahead = header.max_interleave();
+
+    let time_bases: LiteMap<u8, TimeBase> = header
+        .tracks
+        .iter()
+        .map(|stream| (stream.index, stream.time_base()))
+        .collect();
+
+    // a chapter marker on the progress bar for every chapter but the first
+    let chapter_columns: Vec<usize> = header
+        .chapters()
+        .iter()
+        .map(|chapter| Duration::from_micros(chapter.start))
+        .filter(|start| !start.is_zero())
+        .map(|start| {
+            (start.as_secs_f64() / total_duration.as_secs_f64() * video_params.width as f64).round()
+                as usize
+        })
+        .filter(|&column| column < video_params.width as usize)
+        .collect();
+
+    // last keyframe + every delta since, so the whole picture can be redrawn (e.g. when a subtitle goes away)
+    let uses_deltas = video_params.uses_delta_frames();
+    let mut frame_history: Vec<u8> = Vec::new();
+
+    // cell grids are turned into escapes here, diffed against the grid currently on screen
+    let cell_grid = video_params.codec() == VideoCodec::CellGrid;
+    let mut previous_cells: Option<CellFrame> = None;
+    let mut rendered_cells: Vec<u8> = Vec::new();
+
+    let mut info_shown = false;
+    // frames were decoded without being drawn, what's on screen is stale
+    let mut prerolled = false;
+
     'play: loop {
         // wait for play status to shift to true
         let (lock, cvar) = &*state.play_status;
@@ -270,21 +557,14 @@ fn render_loop(
         if cur_state == PlayThreadState::DiscardRequest {
             while let Ok(slot) = receiver.try_recv_ref() {
                 if slot.header.data_type == PacketDataType::Subtitle {
-                    let new_subs: Vec<SubRect> = SubRectVec::decode_from(&mut slot.data.as_slice())
-                        .unwrap()
-                        .into_inner();
-
-                    for sub in new_subs {
-                        subs.push(Subtitle {
-                            stream: slot.header.stream,
-                            subtitle: sub.to_string(),
-                            starts_at: slot.header.timestamp,
-                            ends_at: slot.header.timestamp + slot.header.duration,
-                        });
-                    }
+                    hoard_subtitles(&mut subs, &slot, &time_bases, &state.limits);
                 }
             }
 
+            if let Some(audio) = audio.as_ref() {
+                audio.clear();
+            }
+
             *lock.lock() = PlayThreadState::DiscardDone;
             cvar.notify_all();
 
@@ -295,52 +575,166 @@ fn render_loop(
             break 'play;
         };
 
+        let (packet_start, packet_end) = packet_times(&time_bases, &slot);
+
         if slot.header.data_type == PacketDataType::Subtitle {
-            let new_subs: Vec<SubRect> = SubRectVec::decode_from(&mut slot.data.as_slice())
-                .unwrap()
-                .into_inner();
+            hoard_subtitles(&mut subs, &slot, &time_bases, &state.limits);
 
-            for sub in new_subs {
-                subs.push(Subtitle {
+            continue 'play;
+        }
+
+        // attachments are read up front, see `PlayerControl::cover`
+        if slot.header.data_type == PacketDataType::Attachment {
+            continue 'play;
+        }
+
+        let preroll_until = Duration::from_micros(
+            state
+                .preroll_until
+                .load(std::sync::atomic::Ordering::Acquire),
+        );
+
+        if slot.header.data_type == PacketDataType::Data {
+            // the reader runs ahead, the event waits for its time like a video frame
+            if packet_start >= preroll_until && state.on_data.lock().is_some() {
+                sleeper.sleep_until(*state.current_time.lock() + packet_start);
+            }
+
+            if packet_start >= preroll_until
+                && let Some(callback) = state.on_data.lock().as_mut()
+            {
+                callback(&DataEvent {
                     stream: slot.header.stream,
-                    subtitle: sub.to_string(),
-                    starts_at: slot.header.timestamp,
-                    ends_at: slot.header.timestamp + slot.header.duration,
+                    start: packet_start,
+                    end: packet_end,
+                    data: &slot.data,
                 });
             }
 
             continue 'play;
         }
 
+        if slot.header.data_type == PacketDataType::Audio {
+            if let Some(audio) = audio.as_ref()
+                && audio.stream == slot.header.stream
+                && packet_start >= preroll_until
+            {
+                audio.push(packet_start, &slot.data);
+            }
+
+            continue 'play;
+        }
+
+        // the picture on screen stays, only the overlays get drawn again
+        let repeat = slot.header.data_type == PacketDataType::Repeat;
+
+        if packet_end <= preroll_until {
+            // cell grids are all keyframes, only escape sequence deltas have to be kept
+            if uses_deltas && !repeat {
+                if slot.header.is_keyframe() {
+                    frame_history.clear();
+                }
+
+                frame_history.extend_from_slice(&slot.data);
+                prerolled = true;
+            }
+
+            continue 'play;
+        }
+
         execute!(output, BeginSynchronizedUpdate).unwrap();
 
-        *state.video_time.lock() = slot.header.timestamp;
+        *state.video_time.lock() = packet_start;
         let start = *state.current_time.lock();
-        let line = start + slot.header.timestamp - Duration::from_millis(3);
+        let line = start + packet_start - Duration::from_millis(3);
+
+        let subs_before = subs.num_elements();
+        subs.retain(|sub| {
+            (start + sub.ends_at) >= line
+                && subs_ahead.is_none_or(|ahead| sub.starts_at <= packet_end + ahead)
+        });
+        // hiding the overlay needs a full redraw, same as a subtitle going away
+        let show_info = state.show_info.load(std::sync::atomic::Ordering::Acquire);
+        let full_redraw =
+            subs.num_elements() < subs_before || (info_shown && !show_info) || prerolled;
+        info_shown = show_info;
+        prerolled = false;
+
+        if uses_deltas && !repeat {
+            if slot.header.is_keyframe() {
+                frame_history.clear();
+            }
+
+            frame_history.extend_from_slice(&slot.data);
+        }
+
+        let frame: &[u8] = if repeat {
+            match previous_cells.as_ref() {
+                Some(previous) if cell_grid && full_redraw => {
+                    rendered_cells.clear();
+                    if previous.to_ansi(&mut rendered_cells).is_err() {
+                        rendered_cells.clear();
+                    }
+                    &rendered_cells
+                }
+                _ if uses_deltas && full_redraw && !cell_grid => &frame_history,
+                _ => &[],
+            }
+        } else if cell_grid {
+            rendered_cells.clear();
+
+            let drawn = CellFrame::decode_with_limits(&mut slot.data.as_slice(), &state.limits)
+                .and_then(|cells| {
+                    match previous_cells.as_ref() {
+                        Some(previous) if !full_redraw => {
+                            cells.to_ansi_delta(previous, &mut rendered_cells)?
+                        }
+                        _ => cells.to_ansi(&mut rendered_cells)?,
+                    }
+                    Ok(cells)
+                });
+
+            // a damaged frame (let through by recover mode) is skipped, with nothing to diff against
+            // the next one gets drawn in full
+            match drawn {
+                Ok(cells) => previous_cells = Some(cells),
+                Err(_) => {
+                    rendered_cells.clear();
+                    previous_cells = None;
+                }
+            }
+
+            &rendered_cells
+        } else if uses_deltas && full_redraw {
+            &frame_history
+        } else {
+            &slot.data
+        };
 
         let mut slices: Vec<IoSlice<'_>> =
-            vec![IoSlice::new(b"\x1b[0m\x1b[1;1H"), IoSlice::new(&slot.data)];
+            vec![IoSlice::new(b"\x1b[0m\x1b[1;1H"), IoSlice::new(frame)];
 
-        let bar_filled = ((slot.header.timestamp.as_secs_f64() / total_duration.as_secs_f64())
+        let bar_filled = ((packet_start.as_secs_f64() / total_duration.as_secs_f64())
             * video_params.width as f64)
             .round() as usize;
 
-        let time_bar = format!(
-            "\x1b[0m\x1b[0;32m{}\x1b[0m{}",
-            "■".repeat(bar_filled),
-            "■".repeat(video_params.width as usize - bar_filled)
+        // delta frames leave the cursor wherever the last changed cell was
+        let time_bar = progress_bar(
+            video_params.height / 2 + 1,
+            video_params.width as usize,
+            bar_filled,
+            &chapter_columns,
         );
 
         slices.push(IoSlice::new(time_bar.as_bytes()));
 
         let time_marker = format!(
             "\x1b[0m\n\r{} | {}",
-            FormatDuration(slot.header.timestamp),
+            FormatDuration(packet_start),
             FormatDuration(total_duration)
         );
         slices.push(IoSlice::new(time_marker.as_bytes()));
 
-        subs.retain(|&Subtitle { ends_at, .. }| (start + ends_at) >= line);
         for (
             _,
             &Subtitle {
@@ -351,7 +745,7 @@ fn render_loop(
             },
         ) in &subs
         {
-            if starts_at > slot.header.timestamp + slot.header.duration
+            if starts_at > packet_end
                 || stream
                     != state
                         .subtitle_index
@@ -363,6 +757,10 @@ fn render_loop(
             slices.push(IoSlice::new(subtitle.as_bytes()));
         }
 
+        if show_info {
+            slices.push(IoSlice::new(info.as_bytes()));
+        }
+
         slices.push(IoSlice::new(b"\x1b[0m\n"));
 
         sleeper.sleep_until(line);
@@ -375,6 +773,121 @@ fn render_loop(
     }
 }
 
+/// keeps a subtitle packet's rects until they're shown, a damaged packet (let through by recover mode) is skipped
+fn hoard_subtitles(
+    subs: &mut StableVec<Subtitle>,
+    slot: &PacketWithData,
+    time_bases: &LiteMap<u8, TimeBase>,
+    limits: &DecodeLimits,
+) {
+    let Ok(rects) = SubRectVec::decode_with_limits(&mut slot.data.as_slice(), limits) else {
+        return;
+    };
+
+    let (starts_at, ends_at) = packet_times(time_bases, slot);
+    for sub in rects.into_inner() {
+        subs.push(Subtitle {
+            stream: slot.header.stream,
+            subtitle: sub.to_string(),
+            starts_at,
+            ends_at,
+        });
+    }
+}
+
+/// start and end of a packet on the clock, both from absolute ticks so rounding doesn't add up
+fn packet_times(time_bases: &LiteMap<u8, TimeBase>, slot: &PacketWithData) -> (Duration, Duration) {
+    let time_base = time_bases
+        .get(&slot.header.stream)
+        .copied()
+        .unwrap_or_default();
+
+    (
+        time_base.to_duration(slot.header.timestamp),
+        time_base.to_duration(slot.header.timestamp.saturating_add(slot.header.duration)),
+    )
+}
+
+/// file and stream tags, drawn over the top left corner of the video
+fn info_overlay(
+    header: &FormatData,
+    video: &VideoParameters,
+    audio_error: Option<&anyhow::Error>,
+) -> String {
+    let mut lines = vec![
+        header
+            .tag(tags::TITLE)
+            .unwrap_or(&header.format_name)
+            .to_string(),
+        format!("encoder: {}", header.encoder),
+    ];
+
+    lines.extend(
+        header
+            .tags()
+            .iter()
+            .filter(|tag| tag.key != tags::TITLE)
+            .map(|tag| format!("{}: {}", tag.key, tag.value)),
+    );
+
+    if let Some(e) = audio_error {
+        lines.push(format!("no audio: {e}"));
+    }
+
+    for stream in &header.tracks {
+        lines.push(format!("#{} {}", stream.index, stream.name));
+        lines.extend(
+            stream
+                .tags()
+                .iter()
+                .map(|tag| format!("  {}: {}", tag.key, tag.value)),
+        );
+    }
+
+    // the video is drawn with half blocks, so it's height / 2 rows tall
+    let max_rows = (video.height / 2).saturating_sub(2) as usize;
+
+    let mut overlay = String::new();
+    for (row, line) in lines.iter().take(max_rows).enumerate() {
+        let line: String = line
+            .chars()
+            .take(video.width.saturating_sub(4) as usize)
+            .collect();
+        overlay.push_str(&format!(
+            "\x1b[{};2H\x1b[0m\x1b[48;5;235m\x1b[38;5;252m {line} ",
+            row + 2
+        ));
+    }
+
+    overlay.push_str("\x1b[0m");
+    overlay
+}
+
+fn progress_bar(row: u16, width: usize, filled: usize, chapter_columns: &[usize]) -> String {
+    let mut bar = format!("\x1b[0m\x1b[{row};1H");
+    let mut last_color = "";
+
+    for column in 0..width {
+        let (color, glyph) = if chapter_columns.contains(&column) {
+            ("\x1b[0;33m", "┃")
+        } else if column < filled {
+            ("\x1b[0;32m", "■")
+        } else {
+            ("\x1b[0m", "■")
+        };
+
+        if color != last_color {
+            bar.push_str(color);
+            last_color = color;
+        }
+
+        bar.push_str(glyph);
+    }
+
+    bar.push_str("\x1b[0m");
+    bar
+}
+
 // let mut stdout = BufWriter::with_capacity(192 * 108 * 20, std::io::stdout().lock());
 // stdout.write_all(b"\x1b[1;1H\x1b[?25l")?;
 // stdout.flush()?;